@@ -0,0 +1,1008 @@
+//! A tree representation of a parsed template.
+//!
+//! The tree is built once from a [`TokenStream`] with [`Parser::tree`]. Nesting,
+//! closing and validation all happen here, so everything consuming a template
+//! (like code generation) can work with [`Node`]s instead of raw [`Token`]s.
+use std::collections::HashSet;
+
+use crate::error::{Error, Result};
+use crate::selector::SelectorState;
+use crate::{Parser, Token, TokenStream, TokenType};
+
+/// Tags which start a raw HTML block (`%r:html`).
+pub static RAW_BLOCK_TAG_PREFIX: &str = "r:";
+/// Tags which fill a slot of another template (`%s:file.slot_name`).
+pub static SLOT_FILL_TAG_PREFIX: &str = "s:";
+/// Tags which fill a single named slot of the base template (`%f:slot_name`).
+pub static FILL_BLOCK_TAG_PREFIX: &str = "f:";
+/// Tags which render another template in place (`%include:file(name = value)`).
+pub static INCLUDE_TAG_PREFIX: &str = "include:";
+/// Tags which define a component (`%component:name(param: Type)`).
+pub static COMPONENT_TAG_PREFIX: &str = "component:";
+/// Tags which call a component (`%call:name(param = value)`).
+pub static CALL_TAG_PREFIX: &str = "call:";
+
+/// Elements which can never have children, and are never closed.
+pub static VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// The location of a [`Node`] in its template. Both fields are 1-based.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location {
+    /// The line the node starts on.
+    pub line: usize,
+    /// The column of the first non-whitespace character of the node's line.
+    pub column: usize,
+}
+
+impl Location {
+    /// Get the [`Location`] of a [`Token`].
+    pub fn of(token: &Token) -> Self {
+        Self {
+            line: token.line as usize + 1,
+            column: token.indent.max(0) as usize + 1,
+        }
+    }
+
+    /// Create an [`Error`] pointing at this location.
+    pub fn error(self, message: impl Into<String>, label: impl Into<String>) -> Error {
+        Error::new(message, label).at(self.line, self.column)
+    }
+}
+
+/// An HTML element:
+///
+/// ```text
+/// %div.class#id[attr="value"]
+///     children
+/// ```
+///
+/// The children of an element are the more-indented lines that follow it. The
+/// element is closed once indentation returns to its level, or explicitly with
+/// `%end` or `%-div`.
+#[derive(Debug)]
+pub struct Element {
+    /// The parsed selector of the element.
+    pub selector: SelectorState,
+    /// The text given after a single quote (`%h1'text`), if any.
+    ///
+    /// Elements with inline text are closed on the same line.
+    pub inline: Option<String>,
+    /// The nodes nested in this element.
+    pub children: Vec<Node>,
+    /// The arguments given in parentheses after the selector, if any.
+    ///
+    /// Only components and component calls take arguments.
+    pub arguments: Option<String>,
+    /// Where the element was opened.
+    pub location: Location,
+}
+
+/// A single node in the template tree.
+#[derive(Debug)]
+pub enum Node {
+    /// An HTML [`Element`] and its children.
+    Element(Element),
+    /// A line of text. May contain `{expr}` interpolations.
+    Text { text: String, location: Location },
+    /// A Rust statement (`- let a = 1`).
+    RustStatement { code: String, location: Location },
+    /// A Rust expression which is pushed to the output (`= a` or `!= a`).
+    RustExpr {
+        expr: String,
+        /// If the value should be HTML escaped. `false` for `!=` lines.
+        escape: bool,
+        location: Location,
+    },
+    /// Raw HTML, from `@` lines or `%r:html` blocks.
+    RawHtml { html: String, location: Location },
+    /// A slot other templates can render into (`%slot[name="body"]`).
+    ///
+    /// The children of the slot are its default content, rendered when the slot
+    /// isn't filled.
+    SlotDef {
+        name: String,
+        children: Vec<Node>,
+        location: Location,
+    },
+    /// Content rendered into the slots of another template.
+    ///
+    /// Everything after the fill in the template is a child of it. With a slot name
+    /// (`%s:file.slot_name`), the children are rendered into that slot. Without one
+    /// (`%s:file`), the children are [`Node::Fill`] blocks for any number of slots.
+    SlotFill {
+        template: String,
+        slot: Option<String>,
+        children: Vec<Node>,
+        location: Location,
+    },
+    /// Content rendered into a single named slot of the base template (`%f:slot_name`).
+    Fill {
+        slot: String,
+        children: Vec<Node>,
+        location: Location,
+    },
+    /// The fields a template needs from the struct it is rendered for:
+    ///
+    /// ```text
+    /// %requires(title: &str, count: usize)
+    /// ```
+    ///
+    /// Mostly useful in base templates, which can be used by any number of structs.
+    Requires { params: String, location: Location },
+    /// Another template rendered in place, with the fields it requires given as
+    /// arguments:
+    ///
+    /// ```text
+    /// %include:other(c = 3)
+    /// ```
+    Include {
+        template: String,
+        arguments: String,
+        location: Location,
+    },
+    /// A reusable piece of markup which other templates can call with their own content:
+    ///
+    /// ```text
+    /// %component:card(title: &str)
+    ///     %div.card
+    ///         %h2'{title}
+    ///         %children
+    /// ```
+    ///
+    /// Components only have access to their parameters.
+    Component {
+        name: String,
+        params: String,
+        children: Vec<Node>,
+        location: Location,
+    },
+    /// Where the content given to a component is rendered (`%children`).
+    Children { location: Location },
+    /// A call to a component, in the same template (`%call:card(title = "Title")`) or
+    /// in another template (`%call:file.card(title = "Title")`).
+    ///
+    /// The children of the call are rendered wherever the component has `%children`.
+    Call {
+        template: Option<String>,
+        component: String,
+        arguments: String,
+        children: Vec<Node>,
+        location: Location,
+    },
+}
+
+/// A node which is still accepting children while the tree is built.
+///
+/// Elements and raw blocks keep the indent of the line they were opened on, so they
+/// can be closed once a line with the same (or less) indentation is found. An
+/// indent of `-1` (`%~tag`) means the frame must be closed manually.
+enum Frame {
+    Element(Element, i32),
+    SlotFill {
+        template: String,
+        slot: Option<String>,
+        children: Vec<Node>,
+        location: Location,
+    },
+    /// A raw block, and the (untouched) lines collected in it so far.
+    Raw {
+        tag: String,
+        html: String,
+        location: Location,
+        indent: i32,
+    },
+}
+
+impl Frame {
+    /// Turn the frame into the [`Node`] it represents.
+    fn close(self) -> Node {
+        match self {
+            Frame::Element(element, _) => element.into_node(),
+            Frame::SlotFill {
+                template,
+                slot,
+                children,
+                location,
+            } => Node::SlotFill {
+                template,
+                slot,
+                children,
+                location,
+            },
+            Frame::Raw { html, location, .. } => Node::RawHtml { html, location },
+        }
+    }
+}
+
+impl Element {
+    /// Turn the element into the [`Node`] it represents.
+    ///
+    /// Slots (`%slot`), fill blocks (`%f:slot_name`), components and component calls are
+    /// built like elements, but only their children are kept.
+    fn into_node(mut self) -> Node {
+        let tag = &self.selector.tag;
+
+        if (tag != "slot")
+            && !tag.starts_with(FILL_BLOCK_TAG_PREFIX)
+            && !tag.starts_with(COMPONENT_TAG_PREFIX)
+            && !tag.starts_with(CALL_TAG_PREFIX)
+        {
+            return Node::Element(self);
+        }
+
+        if let Some(inline) = self.inline.take()
+            && !inline.is_empty()
+        {
+            self.children.push(Node::Text {
+                text: inline,
+                location: self.location,
+            });
+        }
+
+        if let Some(slot) = tag.strip_prefix(FILL_BLOCK_TAG_PREFIX) {
+            return Node::Fill {
+                slot: slot.to_string(),
+                children: self.children,
+                location: self.location,
+            };
+        }
+
+        if let Some(name) = tag.strip_prefix(COMPONENT_TAG_PREFIX) {
+            return Node::Component {
+                name: name.to_string(),
+                params: self.arguments.unwrap_or_default(),
+                children: self.children,
+                location: self.location,
+            };
+        }
+
+        if let Some(name) = tag.strip_prefix(CALL_TAG_PREFIX) {
+            // `%call:file.card` calls `card` from `file`
+            let (template, component) = match self.selector.classes.as_ref().and_then(|c| c.first())
+            {
+                Some(component) => (Some(name.to_string()), component.to_owned()),
+                None => (None, name.to_string()),
+            };
+
+            return Node::Call {
+                template,
+                component,
+                arguments: self.arguments.unwrap_or_default(),
+                children: self.children,
+                location: self.location,
+            };
+        }
+
+        Node::SlotDef {
+            name: slot_name(&self.selector).expect("slots are checked for a name"),
+            children: self.children,
+            location: self.location,
+        }
+    }
+}
+
+/// Get the name of a slot from its `selector` (`%slot[name="body"]`).
+fn slot_name(selector: &SelectorState) -> Option<String> {
+    selector
+        .attributes
+        .as_ref()
+        .and_then(|attributes| {
+            attributes
+                .iter()
+                .find_map(|attribute| attribute.strip_prefix("name="))
+        })
+        .map(|name| name.trim_matches('"').to_string())
+}
+
+/// Builds a tree of [`Node`]s from a [`TokenStream`].
+struct Builder {
+    /// The lines of the template, used to keep raw blocks untouched.
+    lines: Vec<String>,
+    /// The lines of the elements closed explicitly at their own indentation level.
+    explicit: HashSet<i32>,
+    root: Vec<Node>,
+    stack: Vec<Frame>,
+}
+
+impl Builder {
+    /// Push a node into the innermost open frame.
+    fn push(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            Some(Frame::Element(element, _)) => element.children.push(node),
+            Some(Frame::SlotFill { children, .. }) => children.push(node),
+            Some(Frame::Raw { .. }) => unreachable!("raw blocks only hold text"),
+            None => self.root.push(node),
+        }
+    }
+
+    /// Close the innermost open frame.
+    fn close(&mut self) {
+        if let Some(frame) = self.stack.pop() {
+            let node = frame.close();
+            self.push(node);
+        }
+    }
+
+    /// Close all elements which were opened with an indent deeper than `indent`.
+    ///
+    /// If `inclusive` is true, elements opened with the same indent are closed as well.
+    fn dedent(&mut self, indent: i32, inclusive: bool) {
+        while let Some(Frame::Element(_, opened)) = self.stack.last() {
+            if (*opened < 0) | (*opened < indent) | (!inclusive & (*opened == indent)) {
+                break;
+            }
+
+            // blank lines at the end of the element belong to its parent
+            let mut trailing: Vec<Node> = Vec::new();
+
+            if let Some(Frame::Element(element, _)) = self.stack.last_mut() {
+                while let Some(Node::Text { text, .. }) = element.children.last()
+                    && text == "\n"
+                {
+                    trailing.extend(element.children.pop());
+                }
+            }
+
+            self.close();
+
+            for node in trailing.into_iter().rev() {
+                self.push(node);
+            }
+        }
+    }
+
+    /// Add a token to the tree.
+    fn token(&mut self, mut token: Token) -> Result<()> {
+        let location = Location::of(&token);
+
+        // blank lines and comments never close anything
+        let blank = (token.r#type == TokenType::Raw) && (token.raw == "\n");
+
+        if let Some(Frame::Raw {
+            tag, html, indent, ..
+        }) = self.stack.last_mut()
+        {
+            // everything is raw html within raw block, until it is closed
+            if let Some(ref selector) = token.selector
+                && selector.tag.strip_prefix('-') == Some(tag.as_str())
+            {
+                self.close();
+                return Ok(());
+            }
+
+            if blank || (*indent < 0) || (token.indent > *indent) {
+                html.push(' ');
+                html.push_str(self.lines[location.line - 1].trim());
+                return Ok(());
+            }
+
+            // indentation returned; the raw block is over
+            self.close();
+        }
+
+        if !blank && (token.indent >= 0) {
+            // explicitly closed elements (`%end` and `%-tag`) close the element
+            // on their own indentation level themselves
+            let explicit = token
+                .selector
+                .as_ref()
+                .is_some_and(|selector| (selector.tag == "end") | selector.tag.starts_with('-'));
+
+            self.dedent(token.indent, !explicit);
+        }
+
+        match token.r#type {
+            TokenType::Comment => {}
+            TokenType::RustString => self.push(Node::RustStatement {
+                code: token.raw,
+                location,
+            }),
+            TokenType::PushedRustString => self.push(Node::RustExpr {
+                expr: token.raw,
+                escape: true,
+                location,
+            }),
+            TokenType::UnescapedRustString => self.push(Node::RustExpr {
+                expr: token.raw,
+                escape: false,
+                location,
+            }),
+            TokenType::Html => self.push(Node::RawHtml {
+                html: token.html,
+                location,
+            }),
+            TokenType::Raw => {
+                if token.raw != " " {
+                    self.push(Node::Text {
+                        text: token.html,
+                        location,
+                    })
+                }
+            }
+            TokenType::Selector => {
+                let selector = token
+                    .selector
+                    .take()
+                    .expect("selector tokens have a selector");
+                self.selector(selector, token, location)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a selector token to the tree.
+    fn selector(
+        &mut self,
+        selector: SelectorState,
+        mut token: Token,
+        location: Location,
+    ) -> Result<()> {
+        if selector.tag == "requires" {
+            let Some(params) = token.arguments.take() else {
+                return Err(location.error(
+                    "`%requires` is missing its fields",
+                    "expected `%requires(name: Type, ...)`",
+                ));
+            };
+
+            if !self.stack.is_empty() {
+                return Err(location.error(
+                    "`%requires` must be at the top level of a template",
+                    "this `%requires` is nested in another element",
+                ));
+            }
+
+            self.push(Node::Requires { params, location });
+            return Ok(());
+        }
+
+        if let Some(template) = selector.tag.strip_prefix(INCLUDE_TAG_PREFIX) {
+            self.push(Node::Include {
+                template: template.to_string(),
+                arguments: token.arguments.take().unwrap_or_default(),
+                location,
+            });
+
+            return Ok(());
+        }
+
+        if selector.tag == "children" {
+            let in_component = self.stack.iter().any(|frame| {
+                matches!(frame, Frame::Element(element, _) if element.selector.tag.starts_with(COMPONENT_TAG_PREFIX))
+            });
+
+            if !in_component {
+                return Err(location.error(
+                    "unexpected `%children`",
+                    "`%children` can only be used in a `%component:`",
+                ));
+            }
+
+            self.push(Node::Children { location });
+            return Ok(());
+        }
+
+        for prefix in [COMPONENT_TAG_PREFIX, CALL_TAG_PREFIX] {
+            if selector.tag.strip_prefix(prefix) == Some("") {
+                return Err(location.error(
+                    format!("`%{prefix}` is missing a component name"),
+                    format!("expected `%{prefix}name(...)`"),
+                ));
+            }
+        }
+
+        if selector.tag.starts_with(COMPONENT_TAG_PREFIX) && !self.stack.is_empty() {
+            return Err(location.error(
+                "components must be defined at the top level of a template",
+                "this component is nested in another element",
+            ));
+        }
+
+        let takes_arguments = selector.tag.starts_with(COMPONENT_TAG_PREFIX)
+            || selector.tag.starts_with(CALL_TAG_PREFIX);
+
+        if token.arguments.is_some() && !takes_arguments {
+            return Err(location.error(
+                format!("unexpected arguments for `%{}`", selector.tag),
+                "only `%requires`, `%include:`, `%component:` and `%call:` take arguments",
+            ));
+        }
+
+        if selector.tag == "end" {
+            // close previous element
+            return match self.stack.last() {
+                Some(Frame::Element(..)) => {
+                    self.close();
+                    Ok(())
+                }
+                _ => Err(location.error("unexpected `%end`", "there is no open element to close")),
+            };
+        }
+
+        if let Some(tag) = selector.tag.strip_prefix('-') {
+            // close previous element, making sure it is the one being closed
+            return match self.stack.last() {
+                Some(Frame::Element(element, _)) if element.selector.tag == tag => {
+                    self.close();
+                    Ok(())
+                }
+                Some(Frame::Element(element, _)) => Err(location.error(
+                    format!("mismatched closing tag `%-{tag}`"),
+                    format!(
+                        "the open element is `%{}` (line {})",
+                        element.selector.tag, element.location.line
+                    ),
+                )),
+                _ => Err(location.error(
+                    format!("unexpected closing tag `%-{tag}`"),
+                    "there is no open element to close",
+                )),
+            };
+        }
+
+        if (selector.tag == "slot") && slot_name(&selector).is_none() {
+            return Err(location.error("slot is missing a name", "expected `%slot[name=\"...\"]`"));
+        }
+
+        if let Some(slot) = selector.tag.strip_prefix(FILL_BLOCK_TAG_PREFIX) {
+            if slot.is_empty() {
+                return Err(location.error(
+                    "fill block is missing a slot name",
+                    "expected `%f:slot_name`",
+                ));
+            }
+
+            // fill blocks go directly into a `%s:template` without a slot name
+            if !matches!(self.stack.as_slice(), [Frame::SlotFill { slot: None, .. }]) {
+                return Err(location.error(
+                    "unexpected fill block",
+                    "fill blocks must be at the top level, after a `%s:template` line",
+                ));
+            }
+        }
+
+        if selector.tag.starts_with(RAW_BLOCK_TAG_PREFIX) {
+            self.stack.push(Frame::Raw {
+                tag: selector.tag,
+                html: String::new(),
+                location,
+                indent: token.indent,
+            });
+
+            return Ok(());
+        }
+
+        if let Some(template) = selector.tag.strip_prefix(SLOT_FILL_TAG_PREFIX) {
+            // this is a slot for accepting another template as a base
+            let slot = selector.classes.as_ref().and_then(|c| c.first()).cloned();
+
+            if !self.stack.is_empty() {
+                return Err(location.error(
+                    "slot fills must be at the top level of a template",
+                    "this slot fill is nested in another element",
+                ));
+            }
+
+            self.stack.push(Frame::SlotFill {
+                template: template.to_string(),
+                slot,
+                children: Vec::new(),
+                location,
+            });
+
+            return Ok(());
+        }
+
+        // inline elements render their text and closing tag after the opening tag
+        let open = selector.clone().render();
+        let close = format!("</{}>", selector.tag);
+        let inline = (token.html != open)
+            .then(|| token.html[open.len()..token.html.len() - close.len()].to_string());
+
+        let element = Element {
+            inline,
+            children: Vec::new(),
+            arguments: token.arguments,
+            location,
+            selector,
+        };
+
+        if element.inline.is_some() || VOID_ELEMENTS.contains(&element.selector.tag.as_str()) {
+            // element closes itself
+            self.push(element.into_node());
+        } else {
+            // elements closed explicitly on their own level are never closed by indentation
+            let indent = match self.explicit.contains(&token.line) {
+                true => -1,
+                false => token.indent,
+            };
+
+            self.stack.push(Frame::Element(element, indent));
+        }
+
+        Ok(())
+    }
+
+    /// Finish building the tree, closing all open elements.
+    fn finish(mut self) -> Result<Vec<Node>> {
+        if let Some(Frame::Raw { tag, location, .. }) = self.stack.last() {
+            return Err(location.error(
+                format!("unclosed raw block `%{tag}`"),
+                format!("expected a closing `%-{tag}`"),
+            ));
+        }
+
+        self.dedent(0, true);
+
+        while !self.stack.is_empty() {
+            self.close();
+        }
+
+        Ok(self.root)
+    }
+}
+
+impl Parser {
+    /// Parse the `input` into a tree of [`Node`]s.
+    pub fn tree(self) -> Result<Vec<Node>> {
+        let lines = self.0.clone();
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut error: Option<Error> = None;
+
+        // read all tokens up to the first invalid one, so closing lines can be looked up
+        let stream: TokenStream = self.parse();
+
+        for token in stream {
+            match token {
+                Ok(token) => tokens.push(token),
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        let mut builder = Builder {
+            lines,
+            explicit: explicitly_closed(&tokens),
+            root: Vec::new(),
+            stack: Vec::new(),
+        };
+
+        for token in tokens {
+            builder.token(token)?;
+        }
+
+        match error {
+            Some(error) => Err(error),
+            None => builder.finish(),
+        }
+    }
+}
+
+/// If the selector `token` opens an element holding the lines which follow it.
+fn opens_element(token: &Token) -> bool {
+    let Some(ref selector) = token.selector else {
+        return false;
+    };
+
+    let tag = selector.tag.as_str();
+    let special = ["requires", "children", "end"].contains(&tag)
+        || tag.starts_with('-')
+        || [
+            INCLUDE_TAG_PREFIX,
+            RAW_BLOCK_TAG_PREFIX,
+            SLOT_FILL_TAG_PREFIX,
+        ]
+        .iter()
+        .any(|prefix| tag.starts_with(prefix));
+
+    // inline elements are closed on their own line
+    !special && !VOID_ELEMENTS.contains(&tag) && (token.html == selector.clone().render())
+}
+
+/// Get the lines of the elements which are closed explicitly (with `%end` or `%-tag`) on
+/// their own indentation level, before indentation returns below it:
+///
+/// ```text
+/// %div
+/// text
+/// %end
+/// ```
+///
+/// These elements hold all lines up to their closing line, like they did before elements
+/// were closed by indentation. Closing lines are paired with the innermost element opened
+/// on their level.
+fn explicitly_closed(tokens: &[Token]) -> HashSet<i32> {
+    let mut explicit = HashSet::new();
+    // the indent, tag, and line of the elements which may still be closed explicitly
+    let mut open: Vec<(i32, &str, i32)> = Vec::new();
+    // the tag and indent of the raw block being skipped
+    let mut raw: Option<(&str, i32)> = None;
+
+    for token in tokens {
+        let blank = (token.r#type == TokenType::Raw) && (token.raw == "\n");
+        let selector = token.selector.as_ref();
+
+        if let Some((tag, indent)) = raw {
+            if selector.is_some_and(|selector| selector.tag.strip_prefix('-') == Some(tag)) {
+                raw = None;
+                continue;
+            }
+
+            if blank || (indent < 0) || (token.indent > indent) {
+                continue;
+            }
+
+            raw = None;
+        }
+
+        if blank {
+            continue;
+        }
+
+        // elements opened deeper than this line are closed by indentation
+        while let Some(&(indent, ..)) = open.last()
+            && (token.indent >= 0)
+            && (indent > token.indent)
+        {
+            open.pop();
+        }
+
+        let Some(selector) = selector else {
+            continue;
+        };
+
+        if (selector.tag == "end") | selector.tag.starts_with('-') {
+            if let Some(&(indent, tag, line)) = open.last()
+                && ((indent == token.indent) | (indent < 0))
+                && ((selector.tag == "end") | (selector.tag.strip_prefix('-') == Some(tag)))
+            {
+                explicit.insert(line);
+                open.pop();
+            }
+        } else if selector.tag.starts_with(RAW_BLOCK_TAG_PREFIX) {
+            raw = Some((&selector.tag, token.indent));
+        } else if opens_element(token) {
+            open.push((token.indent, &selector.tag, token.line));
+        }
+    }
+
+    explicit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(source: &str) -> Result<Vec<Node>> {
+        Parser::new(source.to_string()).tree()
+    }
+
+    /// A compact outline of `nodes`, like `div(p('text') _)`.
+    fn outline(nodes: &[Node]) -> String {
+        let nested = |name: String, children: &[Node]| match children.is_empty() {
+            true => name,
+            false => format!("{name}({})", outline(children)),
+        };
+
+        nodes
+            .iter()
+            .map(|node| match node {
+                Node::Element(element) => nested(element.selector.tag.clone(), &element.children),
+                Node::Text { text, .. } if text == "\n" => "_".to_string(),
+                Node::Text { text, .. } => format!("'{}'", text.trim()),
+                Node::RustStatement { code, .. } => format!("-{}", code.trim()),
+                Node::RustExpr { expr, escape, .. } => {
+                    format!("{}={}", if *escape { "" } else { "!" }, expr.trim())
+                }
+                Node::RawHtml { html, .. } => format!("@{}", html.trim()),
+                Node::SlotDef { name, children, .. } => nested(format!("slot:{name}"), children),
+                Node::SlotFill {
+                    template,
+                    slot,
+                    children,
+                    ..
+                } => match slot {
+                    Some(slot) => nested(format!("s:{template}.{slot}"), children),
+                    None => nested(format!("s:{template}"), children),
+                },
+                Node::Fill { slot, children, .. } => nested(format!("f:{slot}"), children),
+                Node::Requires { params, .. } => format!("requires[{params}]"),
+                Node::Include { template, .. } => format!("include:{template}"),
+                Node::Component { name, children, .. } => {
+                    nested(format!("component:{name}"), children)
+                }
+                Node::Children { .. } => "children".to_string(),
+                Node::Call {
+                    component,
+                    children,
+                    ..
+                } => nested(format!("call:{component}"), children),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn error(source: &str) -> Error {
+        tree(source).expect_err("expected the template to be invalid")
+    }
+
+    #[test]
+    fn nesting() {
+        let nodes = tree("%div\n    %p\n        text\n    %span'inline\n%footer").unwrap();
+        assert_eq!(outline(&nodes), "div(p('text') span) footer");
+    }
+
+    #[test]
+    fn inline_and_void_elements() {
+        let nodes = tree("%ul\n    %li'one\n    %br\n    %li'two").unwrap();
+        assert_eq!(outline(&nodes), "ul(li br li)");
+
+        let Node::Element(ref list) = nodes[0] else {
+            panic!("expected an element");
+        };
+
+        let Node::Element(ref item) = list.children[0] else {
+            panic!("expected an element");
+        };
+
+        assert_eq!(item.inline.as_deref(), Some("one"));
+    }
+
+    #[test]
+    fn rust_lines() {
+        let nodes = tree("- if a {\n    %p'{a}\n- }\n= a\n!= b").unwrap();
+        assert_eq!(outline(&nodes), "-if a { p -} =a !=b");
+    }
+
+    #[test]
+    fn end_at_opener_indent() {
+        let nodes = tree("%div\n    %p'a\n%end\n%span").unwrap();
+        assert_eq!(outline(&nodes), "div(p) span");
+
+        // deeper elements are closed by indentation first
+        let nodes = tree("%div\n    %section\n        %p'a\n%end\n%span").unwrap();
+        assert_eq!(outline(&nodes), "div(section(p)) span");
+
+        let nodes = tree("%div\n    %section\n        %p'a\n%-div").unwrap();
+        assert_eq!(outline(&nodes), "div(section(p))");
+    }
+
+    #[test]
+    fn whitespace_sensitive_frames_block_dedent() {
+        let nodes = tree("%~div\n%p'a\n%span'b\n%-div\n%footer").unwrap();
+        assert_eq!(outline(&nodes), "div(p span) footer");
+
+        // elements outside of the frame aren't closed by indentation either
+        let nodes = tree("%div\n    %~section\n%p'x\n%-section\n%-div").unwrap();
+        assert_eq!(outline(&nodes), "div(section(p))");
+    }
+
+    #[test]
+    fn trailing_blank_lines_move_to_parent() {
+        let nodes = tree("%div\n    %p'a\n\n\n%span").unwrap();
+        assert_eq!(outline(&nodes), "div(p) _ _ span");
+
+        let nodes = tree("%main\n    %div\n        %p'a\n\n    %span").unwrap();
+        assert_eq!(outline(&nodes), "main(div(p) _ span)");
+
+        // blank lines between children stay in the element
+        let nodes = tree("%div\n    %p'a\n\n    %p'b").unwrap();
+        assert_eq!(outline(&nodes), "div(p _ p)");
+    }
+
+    #[test]
+    fn rust_lines_close_nested_elements() {
+        let nodes = tree("- if a {\n    %div\n        %p'a\n- }\n%span").unwrap();
+        assert_eq!(outline(&nodes), "-if a { div(p) -} span");
+
+        let nodes =
+            tree("%ul\n    - for i in 0..3 {\n        %li\n            {i}\n    - }").unwrap();
+        assert_eq!(outline(&nodes), "ul(-for i in 0..3 { li('{i}') -})");
+    }
+
+    #[test]
+    fn flat_explicit_closing() {
+        let nodes = tree("%div\ntext\n%end\n%span").unwrap();
+        assert_eq!(outline(&nodes), "div('text') span");
+
+        let nodes = tree("%div\n%p'a\n%-div\n%span").unwrap();
+        assert_eq!(outline(&nodes), "div(p) span");
+
+        // closing lines pair with the innermost element on their level
+        let nodes = tree("%div\n%p\ntext\n%end\n%end").unwrap();
+        assert_eq!(outline(&nodes), "div(p('text'))");
+
+        // elements without a closing line on their level are still closed by indentation
+        let nodes = tree("%main\n%div\ntext\n%end").unwrap();
+        assert_eq!(outline(&nodes), "main div('text')");
+
+        let nodes = tree("%section\n    %div\n    %p'a\n    %end\n%footer").unwrap();
+        assert_eq!(outline(&nodes), "section(div(p)) footer");
+    }
+
+    #[test]
+    fn explicit_closing() {
+        let nodes = tree("%div\n    %p\n    %end\n%-div\n%span").unwrap();
+        assert_eq!(outline(&nodes), "div(p) span");
+    }
+
+    #[test]
+    fn mismatched_closing_tag() {
+        let error = error("%div\n    %p\n    %-div");
+        assert_eq!(error.message, "mismatched closing tag `%-div`");
+        assert_eq!((error.line, error.column), (3, 5));
+    }
+
+    #[test]
+    fn unexpected_closing() {
+        assert_eq!(error("%p'text\n%end").message, "unexpected `%end`");
+        assert_eq!(error("%-div").message, "unexpected closing tag `%-div`");
+    }
+
+    #[test]
+    fn raw_blocks() {
+        let nodes =
+            tree("%r:html\n    <b>{not interpolated}</b>\n\n    <i>\n%-r:html\n%p").unwrap();
+        assert_eq!(outline(&nodes), "@<b>{not interpolated}</b>  <i> p");
+    }
+
+    #[test]
+    fn raw_blocks_end_with_indentation() {
+        let nodes = tree("%div\n    %r:html\n        <b>\n    %p").unwrap();
+        assert_eq!(outline(&nodes), "div(@<b> p)");
+    }
+
+    #[test]
+    fn unclosed_raw_block() {
+        let error = error("%~r:html\n<b>");
+        assert_eq!(error.message, "unclosed raw block `%r:html`");
+        assert_eq!(error.line, 1);
+    }
+
+    #[test]
+    fn slots() {
+        let nodes = tree("%main\n    %slot[name=\"body\"]\n        %p'default").unwrap();
+        assert_eq!(outline(&nodes), "main(slot:body(p))");
+
+        assert_eq!(error("%slot").message, "slot is missing a name");
+    }
+
+    #[test]
+    fn slot_fills() {
+        let nodes = tree("%s:base.body\n%p'one\n%p'two").unwrap();
+        assert_eq!(outline(&nodes), "s:base.body(p p)");
+
+        let nodes = tree("%s:base\n- let a = 1\n%f:title'{a}\n%f:body\n    %p").unwrap();
+        assert_eq!(
+            outline(&nodes),
+            "s:base(-let a = 1 f:title('{a}') f:body(p))"
+        );
+    }
+
+    #[test]
+    fn misplaced_fills() {
+        assert_eq!(error("%f:title").message, "unexpected fill block");
+        assert_eq!(
+            error("%s:base.body\n%f:title").message,
+            "unexpected fill block"
+        );
+        assert_eq!(
+            error("%s:base\n%f:title\n    %f:body").message,
+            "unexpected fill block"
+        );
+        assert_eq!(
+            error("%s:base\n%f:").message,
+            "fill block is missing a slot name"
+        );
+        assert_eq!(
+            error("%div\n    %s:base").message,
+            "slot fills must be at the top level of a template"
+        );
+    }
+}
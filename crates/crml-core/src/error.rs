@@ -0,0 +1,99 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// A [`Result`](std::result::Result) with an [`Error`].
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An error found while parsing or generating a template.
+///
+/// Lines and columns are 1-based, as they would be shown in an editor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    /// The name of the template file the error was found in.
+    pub file: Option<String>,
+    /// The line the error was found on.
+    pub line: usize,
+    /// The column the error was found at.
+    pub column: usize,
+    /// What went wrong.
+    pub message: String,
+    /// A short note describing the location the error points to.
+    pub label: String,
+    /// The template line the error was found on, quoted when the error is shown.
+    pub source: Option<String>,
+}
+
+impl Error {
+    /// Create a new [`Error`] with the given `message` and `label`.
+    ///
+    /// The error points to the start of the template until it is moved with
+    /// [`Error::at`].
+    pub fn new(message: impl Into<String>, label: impl Into<String>) -> Self {
+        Self {
+            file: None,
+            line: 1,
+            column: 1,
+            message: message.into(),
+            label: label.into(),
+            source: None,
+        }
+    }
+
+    /// Point the error at the given `line` and `column`.
+    pub fn at(mut self, line: usize, column: usize) -> Self {
+        self.line = line;
+        self.column = column;
+        self
+    }
+
+    /// Set the file of the error (if it doesn't have one yet).
+    pub fn in_file(mut self, file: impl Into<String>) -> Self {
+        if self.file.is_none() {
+            self.file = Some(file.into());
+        }
+
+        self
+    }
+
+    /// Set the source line of the error (if it doesn't have one yet).
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        if self.source.is_none() {
+            self.source = Some(source.into());
+        }
+
+        self
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Some(ref file) = self.file else {
+            // not found in a template; there is no location to show
+            return write!(f, "{}\n  = {}", self.message, self.label);
+        };
+
+        let Some(ref source) = self.source else {
+            return write!(
+                f,
+                "{}\n --> {file}:{}:{}\n  = {}",
+                self.message, self.line, self.column, self.label
+            );
+        };
+
+        // quote the source line, like rustc does
+        let gutter = " ".repeat(self.line.to_string().len());
+        let source = source.trim_end().replace('\t', " ");
+
+        write!(
+            f,
+            "{}\n{gutter}--> {file}:{}:{}\n{gutter} |\n{} | {source}\n{gutter} | {}^ {}",
+            self.message,
+            self.line,
+            self.column,
+            self.line,
+            " ".repeat(self.column.saturating_sub(1)),
+            self.label
+        )
+    }
+}
+
+impl std::error::Error for Error {}
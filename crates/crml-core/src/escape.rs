@@ -0,0 +1,205 @@
+//! HTML escaping for interpolated values.
+//!
+//! Generated template code calls into this module for every `{expr}`
+//! interpolation and every `= expr` line, unless the template explicitly opts
+//! out with `{!= expr}` or `!= expr`. Values wrapped in [`Raw`] (like fields marked
+//! with `#[crml(escape = false)]`) are never escaped.
+use std::fmt::{Display, Result, Write};
+use std::ops::Deref;
+
+/// The HTML context a value is being written into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Context {
+    /// Element content. Escapes `&`, `<` and `>`.
+    Text,
+    /// An attribute value. Escapes `&`, `<`, `>`, `"`, `'`, and (so values can't end an
+    /// unquoted attribute) whitespace, `=` and `` ` ``.
+    Attribute,
+}
+
+impl Context {
+    /// Get the escaped form of `char` in this context, if it needs escaping.
+    pub fn escape_char(self, char: char) -> Option<&'static str> {
+        match (self, char) {
+            (_, '&') => Some("&amp;"),
+            (_, '<') => Some("&lt;"),
+            (_, '>') => Some("&gt;"),
+            (Context::Attribute, '"') => Some("&quot;"),
+            (Context::Attribute, '\'') => Some("&#x27;"),
+            (Context::Attribute, '=') => Some("&#x3D;"),
+            (Context::Attribute, '`') => Some("&#x60;"),
+            (Context::Attribute, ' ') => Some("&#x20;"),
+            (Context::Attribute, '\t') => Some("&#x9;"),
+            (Context::Attribute, '\n') => Some("&#xA;"),
+            (Context::Attribute, '\x0C') => Some("&#xC;"),
+            (Context::Attribute, '\r') => Some("&#xD;"),
+            _ => None,
+        }
+    }
+}
+
+/// A [`Write`] adapter which escapes everything written through it.
+pub struct Escaper<'a, W: Write + ?Sized> {
+    inner: &'a mut W,
+    context: Context,
+}
+
+impl<'a, W: Write + ?Sized> Escaper<'a, W> {
+    /// Create a new [`Escaper`] writing into `inner`.
+    pub fn new(inner: &'a mut W, context: Context) -> Self {
+        Self { inner, context }
+    }
+}
+
+impl<W: Write + ?Sized> Write for Escaper<'_, W> {
+    fn write_str(&mut self, s: &str) -> Result {
+        let mut last = 0;
+
+        for (i, char) in s.char_indices() {
+            if let Some(escaped) = self.context.escape_char(char) {
+                self.inner.write_str(&s[last..i])?;
+                self.inner.write_str(escaped)?;
+                last = i + char.len_utf8();
+            }
+        }
+
+        self.inner.write_str(&s[last..])
+    }
+}
+
+/// Trusted markup, which is written as it is even where values are escaped.
+///
+/// Fields marked with `#[crml(escape = false)]` are bound as `Raw(&field)` in templates.
+/// Anything computed from the value (like `body.trim()`) is a new value, and escaped
+/// as usual.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Raw<T>(pub T);
+
+impl<T> Deref for Raw<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// A value which can be written into the output of a template.
+///
+/// Implemented for every [`Display`] value, which is escaped when it is written, and
+/// for [`Raw`] values, which never are.
+pub trait Value {
+    /// Write the value into `out`, escaped for the given `context`.
+    fn write_escaped<W: Write + ?Sized>(&self, out: &mut W, context: Context) -> Result;
+
+    /// Write the value into `out` without escaping it.
+    fn write_raw<W: Write + ?Sized>(&self, out: &mut W) -> Result;
+}
+
+impl<T: Display + ?Sized> Value for T {
+    fn write_escaped<W: Write + ?Sized>(&self, out: &mut W, context: Context) -> Result {
+        write!(Escaper::new(out, context), "{self}")
+    }
+
+    fn write_raw<W: Write + ?Sized>(&self, out: &mut W) -> Result {
+        write!(out, "{self}")
+    }
+}
+
+impl<T: Display> Value for Raw<T> {
+    fn write_escaped<W: Write + ?Sized>(&self, out: &mut W, _: Context) -> Result {
+        self.write_raw(out)
+    }
+
+    fn write_raw<W: Write + ?Sized>(&self, out: &mut W) -> Result {
+        write!(out, "{}", self.0)
+    }
+}
+
+/// Write `value` into `out`, escaped for the given `context` (unless it is [`Raw`]).
+pub fn write_escaped<W: Write + ?Sized, T: Value + ?Sized>(
+    out: &mut W,
+    value: &T,
+    context: Context,
+) -> Result {
+    value.write_escaped(out, context)
+}
+
+/// Write `value` into `out` without escaping it.
+///
+/// Only used for trusted markup (`{!= expr}` and `!= expr`).
+pub fn write_raw<W: Write + ?Sized, T: Value + ?Sized>(out: &mut W, value: &T) -> Result {
+    value.write_raw(out)
+}
+
+/// Escape `input` for the given `context`.
+pub fn escape(input: &str, context: Context) -> String {
+    let mut out = String::with_capacity(input.len());
+    write_escaped(&mut out, input, context).expect("writing to a String cannot fail");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_char() {
+        assert_eq!(Context::Text.escape_char('<'), Some("&lt;"));
+        assert_eq!(Context::Text.escape_char('"'), None);
+        assert_eq!(Context::Text.escape_char('\''), None);
+        assert_eq!(Context::Attribute.escape_char('"'), Some("&quot;"));
+        assert_eq!(Context::Attribute.escape_char('\''), Some("&#x27;"));
+        assert_eq!(Context::Attribute.escape_char('a'), None);
+    }
+
+    #[test]
+    fn escape_text() {
+        assert_eq!(
+            escape(r#"<a href="x">&'</a>"#, Context::Text),
+            r#"&lt;a href="x"&gt;&amp;'&lt;/a&gt;"#
+        );
+    }
+
+    #[test]
+    fn escape_attribute() {
+        assert_eq!(
+            escape(r#"x" onmouseover='alert(1)'"#, Context::Attribute),
+            "x&quot;&#x20;onmouseover&#x3D;&#x27;alert(1)&#x27;"
+        );
+    }
+
+    #[test]
+    fn escape_unquoted_attribute() {
+        assert_eq!(
+            escape("x onmouseover=alert(1)\t`a`", Context::Attribute),
+            "x&#x20;onmouseover&#x3D;alert(1)&#x9;&#x60;a&#x60;"
+        );
+        assert_eq!(escape("x y=z", Context::Text), "x y=z");
+    }
+
+    #[test]
+    fn escape_unicode() {
+        assert_eq!(escape("ü<ß>", Context::Text), "ü&lt;ß&gt;");
+    }
+
+    #[test]
+    fn raw() {
+        let mut out = String::new();
+        write_raw(&mut out, "<b>").unwrap();
+        assert_eq!(out, "<b>");
+    }
+
+    #[test]
+    fn raw_values_are_never_escaped() {
+        let body = String::from("<b>bold</b>");
+        let mut out = String::new();
+        write_escaped(&mut out, &Raw(&body), Context::Text).unwrap();
+        write_escaped(&mut out, &Raw(&body), Context::Attribute).unwrap();
+        assert_eq!(out, "<b>bold</b><b>bold</b>");
+
+        // values computed from a raw value are escaped as usual
+        let mut out = String::new();
+        write_escaped(&mut out, Raw(&body).as_str(), Context::Text).unwrap();
+        assert_eq!(out, "&lt;b&gt;bold&lt;/b&gt;");
+    }
+}
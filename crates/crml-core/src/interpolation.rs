@@ -0,0 +1,244 @@
+//! Splitting of template text into static and interpolated segments.
+use crate::error::{Error, Result};
+use crate::escape::Context;
+
+/// A piece of a line of template text.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Segment {
+    /// Static text which is pushed to the output as is.
+    Literal(String),
+    /// An interpolated Rust expression:
+    ///
+    /// ```text
+    /// a is {a}
+    /// ```
+    ///
+    /// Expressions are escaped for their [`Context`] unless they begin with
+    /// `!=` (`{!= a}`, like `!= a` lines), which marks them as trusted markup.
+    /// A single `!` is Rust's negation (`{!done}`).
+    Expr {
+        expr: String,
+        context: Context,
+        escape: bool,
+    },
+}
+
+/// Split `input` into [`Segment`]s.
+///
+/// `{{` and `}}` are literal braces. Interpolations found inside of an element's
+/// opening tag (between `<` and `>`) are escaped as attribute values, everything
+/// else is escaped as text. A `>` inside of a quoted attribute value doesn't end
+/// the tag.
+///
+/// Columns in returned errors are relative to the start of `input`.
+pub fn segments(input: &str) -> Result<Vec<Segment>> {
+    let mut out: Vec<Segment> = Vec::new();
+    let mut literal = String::new();
+    let mut in_tag: bool = false;
+    // the quote of the attribute value we're in, while in a tag
+    let mut quote: Option<char> = None;
+    let mut chars = input.chars().enumerate().peekable();
+
+    while let Some((column, char)) = chars.next() {
+        match char {
+            '{' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek().map(|(_, c)| *c) == Some('}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '}' => {
+                return Err(Error::new(
+                    "unexpected `}`",
+                    "no interpolation was opened (use `}}` for a literal brace)",
+                )
+                .at(1, column + 1));
+            }
+            '{' => {
+                // read expression until the matching closing brace
+                let mut expr = String::new();
+                let mut depth: i32 = 0;
+                let mut in_string: bool = false;
+                let mut closed: bool = false;
+
+                while let Some((_, char)) = chars.next() {
+                    if in_string {
+                        if char == '\\' {
+                            expr.push(char);
+
+                            if let Some((_, escaped)) = chars.next() {
+                                expr.push(escaped);
+                            }
+
+                            continue;
+                        } else if char == '"' {
+                            in_string = false;
+                        }
+                    } else {
+                        match char {
+                            '"' => in_string = true,
+                            '{' => depth += 1,
+                            '}' if depth == 0 => {
+                                closed = true;
+                                break;
+                            }
+                            '}' => depth -= 1,
+                            _ => {}
+                        }
+                    }
+
+                    expr.push(char);
+                }
+
+                if !closed {
+                    return Err(Error::new(
+                        "unclosed interpolation",
+                        "expected a closing `}` (use `{{` for a literal brace)",
+                    )
+                    .at(1, column + 1));
+                }
+
+                if !literal.is_empty() {
+                    out.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+
+                // `!=` can't begin a Rust expression, unlike `!`
+                let (expr, escape) = match expr.trim_start().strip_prefix("!=") {
+                    Some(expr) => (expr, false),
+                    None => (expr.as_str(), true),
+                };
+
+                out.push(Segment::Expr {
+                    expr: expr.trim().to_string(),
+                    context: if in_tag {
+                        Context::Attribute
+                    } else {
+                        Context::Text
+                    },
+                    escape,
+                });
+            }
+            _ => {
+                match (in_tag, quote, char) {
+                    (false, _, '<') => in_tag = true,
+                    (true, Some(open), _) if char == open => quote = None,
+                    (true, None, '"' | '\'') => quote = Some(char),
+                    (true, None, '>') => in_tag = false,
+                    _ => {}
+                }
+
+                literal.push(char);
+            }
+        }
+    }
+
+    if !literal.is_empty() {
+        out.push(Segment::Literal(literal));
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expr(expr: &str, context: Context, escape: bool) -> Segment {
+        Segment::Expr {
+            expr: expr.to_string(),
+            context,
+            escape,
+        }
+    }
+
+    #[test]
+    fn literal_braces() {
+        assert_eq!(
+            segments("a {{b}} c").unwrap(),
+            vec![Segment::Literal("a {b} c".to_string())]
+        );
+    }
+
+    #[test]
+    fn text_interpolation() {
+        assert_eq!(
+            segments("a is { a }!").unwrap(),
+            vec![
+                Segment::Literal("a is ".to_string()),
+                expr("a", Context::Text, true),
+                Segment::Literal("!".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_interpolation() {
+        assert_eq!(
+            segments("{!= html}").unwrap(),
+            vec![expr("html", Context::Text, false)]
+        );
+        assert_eq!(
+            segments("{!done}").unwrap(),
+            vec![expr("!done", Context::Text, true)]
+        );
+        assert_eq!(
+            segments("{ !=html}").unwrap(),
+            vec![expr("html", Context::Text, false)]
+        );
+    }
+
+    #[test]
+    fn nested_braces_and_strings() {
+        assert_eq!(
+            segments(r#"{format!("{}}", Foo { a: 1 }.a)}"#).unwrap(),
+            vec![expr(
+                r#"format!("{}}", Foo { a: 1 }.a)"#,
+                Context::Text,
+                true
+            )]
+        );
+    }
+
+    #[test]
+    fn attribute_context() {
+        assert_eq!(
+            segments(r#"<a href="{url}">{text}</a>"#).unwrap(),
+            vec![
+                Segment::Literal(r#"<a href=""#.to_string()),
+                expr("url", Context::Attribute, true),
+                Segment::Literal(r#"">"#.to_string()),
+                expr("text", Context::Text, true),
+                Segment::Literal("</a>".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn quoted_gt_stays_in_tag() {
+        let segments = segments(r#"<a title="a>b" data-x='c>d' href="{u}">{t}"#).unwrap();
+        let contexts: Vec<Context> = segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Expr { context, .. } => Some(*context),
+                Segment::Literal(_) => None,
+            })
+            .collect();
+
+        assert_eq!(contexts, vec![Context::Attribute, Context::Text]);
+    }
+
+    #[test]
+    fn unexpected_closing_brace() {
+        let error = segments("ab}").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 3));
+    }
+
+    #[test]
+    fn unclosed_interpolation() {
+        let error = segments("a {b").unwrap_err();
+        assert_eq!(error.message, "unclosed interpolation");
+        assert_eq!(error.column, 3);
+    }
+}
@@ -1,9 +1,144 @@
+pub mod ast;
+pub mod error;
+pub mod escape;
+pub mod interpolation;
 pub mod selector;
+#[cfg(feature = "async")]
+pub mod stream;
+pub use error::{Error, Result};
 use selector::{Selector, SelectorState};
 
 /// A trait to render template structs.
 pub trait Template {
-    fn render(self) -> String;
+    /// The number of bytes of static text in the template.
+    ///
+    /// Rendered strings are allocated with (at least) this capacity.
+    const SIZE_HINT: usize = 0;
+
+    /// Render the template into the given `out` buffer.
+    fn render_into<W: std::fmt::Write + ?Sized>(&self, out: &mut W) -> std::fmt::Result;
+
+    /// Render the template into the given [`std::io::Write`] `out`.
+    fn render_into_io<W: std::io::Write + ?Sized>(&self, out: &mut W) -> std::io::Result<()> {
+        let mut adapter = IoWriter {
+            inner: out,
+            error: None,
+        };
+
+        match self.render_into(&mut adapter) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(adapter
+                .error
+                .unwrap_or_else(|| std::io::Error::other("failed to render template"))),
+        }
+    }
+
+    /// Render the template into the given [`AsyncWrite`](stream::AsyncWrite) `out`.
+    ///
+    /// Templates generated by the `template` macro write their output in chunks, at
+    /// element boundaries. Other implementations render the full template first.
+    #[cfg(feature = "async")]
+    fn render_into_async<W: stream::AsyncWrite + Unpin + ?Sized>(
+        &self,
+        out: &mut W,
+    ) -> impl Future<Output = std::io::Result<()>> {
+        async move { stream::write_all(out, self.render().as_bytes()).await }
+    }
+
+    /// Render the template to a [`String`].
+    fn render(&self) -> String {
+        let mut out = String::with_capacity(Self::SIZE_HINT);
+        self.render_into(&mut out)
+            .expect("failed to render template");
+        out
+    }
+
+    /// Render the template to a [`String`], consuming it.
+    fn into_html(self) -> String
+    where
+        Self: Sized,
+    {
+        self.render()
+    }
+}
+
+impl<T: Template> Template for &T {
+    const SIZE_HINT: usize = T::SIZE_HINT;
+
+    fn render_into<W: std::fmt::Write + ?Sized>(&self, out: &mut W) -> std::fmt::Result {
+        (**self).render_into(out)
+    }
+
+    #[cfg(feature = "async")]
+    fn render_into_async<W: stream::AsyncWrite + Unpin + ?Sized>(
+        &self,
+        out: &mut W,
+    ) -> impl Future<Output = std::io::Result<()>> {
+        (**self).render_into_async(out)
+    }
+}
+
+impl<T: Template> Template for Box<T> {
+    const SIZE_HINT: usize = T::SIZE_HINT;
+
+    fn render_into<W: std::fmt::Write + ?Sized>(&self, out: &mut W) -> std::fmt::Result {
+        (**self).render_into(out)
+    }
+
+    #[cfg(feature = "async")]
+    fn render_into_async<W: stream::AsyncWrite + Unpin + ?Sized>(
+        &self,
+        out: &mut W,
+    ) -> impl Future<Output = std::io::Result<()>> {
+        (**self).render_into_async(out)
+    }
+}
+
+impl<T: Template> Template for std::rc::Rc<T> {
+    const SIZE_HINT: usize = T::SIZE_HINT;
+
+    fn render_into<W: std::fmt::Write + ?Sized>(&self, out: &mut W) -> std::fmt::Result {
+        (**self).render_into(out)
+    }
+
+    #[cfg(feature = "async")]
+    fn render_into_async<W: stream::AsyncWrite + Unpin + ?Sized>(
+        &self,
+        out: &mut W,
+    ) -> impl Future<Output = std::io::Result<()>> {
+        (**self).render_into_async(out)
+    }
+}
+
+impl<T: Template> Template for std::sync::Arc<T> {
+    const SIZE_HINT: usize = T::SIZE_HINT;
+
+    fn render_into<W: std::fmt::Write + ?Sized>(&self, out: &mut W) -> std::fmt::Result {
+        (**self).render_into(out)
+    }
+
+    #[cfg(feature = "async")]
+    fn render_into_async<W: stream::AsyncWrite + Unpin + ?Sized>(
+        &self,
+        out: &mut W,
+    ) -> impl Future<Output = std::io::Result<()>> {
+        (**self).render_into_async(out)
+    }
+}
+
+/// Adapts a [`std::io::Write`] into a [`std::fmt::Write`], keeping the last IO error.
+struct IoWriter<'a, W: std::io::Write + ?Sized> {
+    inner: &'a mut W,
+    error: Option<std::io::Error>,
+}
+
+impl<W: std::io::Write + ?Sized> std::fmt::Write for IoWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            std::fmt::Error
+        })
+    }
 }
 
 /// The type of a given [`Token`].
@@ -33,8 +168,17 @@ pub enum TokenType {
     /// = get_new_string()
     /// ```
     ///
-    /// Begins with `=`.
+    /// Begins with `=`. The value is HTML escaped.
     PushedRustString,
+    /// A direct string of Rust code which is pushed to the output HTML *without*
+    /// being escaped:
+    ///
+    /// ```text
+    /// != OtherProps { c: 3 }.render()
+    /// ```
+    ///
+    /// Begins with `!=`. Only use this for trusted markup.
+    UnescapedRustString,
     /// A CSS selector which will be transformed into an HTML element:
     ///
     /// ```text
@@ -76,6 +220,9 @@ pub struct Token {
     pub line: i32,
     /// The selector of the token. Only applies to [`TokenType::Selector`].
     pub selector: Option<SelectorState>,
+    /// The arguments given in parentheses after the selector (`%requires(title: &str)`).
+    /// Only applies to [`TokenType::Selector`].
+    pub arguments: Option<String>,
 }
 
 impl Token {
@@ -88,72 +235,76 @@ impl Token {
             indent,
             line,
             selector: None,
+            arguments: None,
         }
     }
 
     /// Create a [`Token`] from a given [`String`] value,
-    pub fn from_string(value: String, indent: i32, line: i32) -> Option<Self> {
+    pub fn from_string(value: String, indent: i32, line: i32) -> Result<Self> {
         let mut chars = value.chars();
 
         match match chars.next() {
             Some(c) => c,
             None => {
-                return Some(Self::from_indent_ln(indent, line));
+                return Ok(Self::from_indent_ln(indent, line));
             }
         } {
             '/' => {
                 // comment; ignore
-                if let Some(char) = chars.next() {
-                    if char == '>' {
-                        // raw html element closing, NOT COMMENT!
-                        return Some(Self {
-                            r#type: TokenType::Raw,
-                            raw: value.clone(),
-                            html: value,
-                            indent,
-                            line,
-                            selector: None,
-                        });
-                    }
+                if let Some(char) = chars.next()
+                    && char == '>'
+                {
+                    // raw html element closing, NOT COMMENT!
+                    return Ok(Self {
+                        r#type: TokenType::Raw,
+                        raw: value.clone(),
+                        html: value,
+                        indent,
+                        line,
+                        selector: None,
+                        arguments: None,
+                    });
                 }
 
-                return Some(Self::from_indent_ln(indent, line));
+                Ok(Self::from_indent_ln(indent, line))
             }
             '-' => {
                 // starting with an opening sign; rust data
                 // not much real parsing to do here
                 let mut raw = String::new();
 
-                while let Some(char) = chars.next() {
+                for char in chars.by_ref() {
                     raw.push(char);
                 }
 
-                return Some(Self {
+                Ok(Self {
                     r#type: TokenType::RustString,
                     raw,
                     html: String::new(),
                     indent,
                     line,
                     selector: None,
-                });
+                    arguments: None,
+                })
             }
             '=' => {
                 // starting with an opening sign; rust data
                 // not much real parsing to do here
                 let mut raw = String::new();
 
-                while let Some(char) = chars.next() {
+                for char in chars.by_ref() {
                     raw.push(char);
                 }
 
-                return Some(Self {
+                Ok(Self {
                     r#type: TokenType::PushedRustString,
                     raw,
                     html: String::new(),
                     indent,
                     line,
                     selector: None,
-                });
+                    arguments: None,
+                })
             }
             '%' => {
                 // starting with a beginning sign; selector
@@ -161,15 +312,40 @@ impl Token {
                 let mut data = String::new();
                 let mut inline: bool = false;
                 let mut whitespace_sensitive: bool = false;
+                let mut arguments: Option<String> = None;
+                // attribute brackets (and the quote of the value in them) we're in
+                let mut in_brackets: bool = false;
+                let mut quote: Option<char> = None;
 
                 while let Some(char) = chars.next() {
+                    if in_brackets {
+                        // attribute values are taken as they are
+                        match (quote, char) {
+                            (Some(open), _) if char == open => quote = None,
+                            (None, '"' | '\'') => quote = Some(char),
+                            (None, ']') => in_brackets = false,
+                            _ => {}
+                        }
+
+                        raw.push(char);
+                        continue;
+                    }
+
                     // check for inline char (single quote)
-                    if char == '\'' {
+                    if char == '[' {
+                        in_brackets = true;
+                    } else if char == '\'' {
                         inline = true;
                         break;
                     } else if char == '~' {
                         whitespace_sensitive = true;
                         continue;
+                    } else if (char == '(') && arguments.is_none() {
+                        arguments = Some(read_arguments(&mut chars).ok_or_else(|| {
+                            Error::new("unclosed arguments", "expected a closing `)`")
+                                .at(line as usize + 1, indent as usize + raw.len() + 2)
+                        })?);
+                        continue;
                     }
 
                     // push char
@@ -177,13 +353,17 @@ impl Token {
                 }
 
                 if inline {
-                    while let Some(char) = chars.next() {
+                    for char in chars.by_ref() {
                         data.push(char);
                     }
                 }
 
-                let selector = Selector::new(raw.clone()).parse();
-                return Some(Self {
+                let selector = Selector::new(raw.clone()).parse().map_err(|e| {
+                    // point into the full line (after the indent and `%`)
+                    let column = e.column + indent as usize + 1;
+                    e.at(line as usize + 1, column)
+                })?;
+                Ok(Self {
                     r#type: TokenType::Selector,
                     raw: format!("{raw}{data}"),
                     html: if inline {
@@ -195,29 +375,43 @@ impl Token {
                     indent: if whitespace_sensitive { -1 } else { indent },
                     line,
                     selector: Some(selector),
-                });
+                    arguments,
+                })
+            }
+            '!' if value.starts_with("!=") => {
+                // starting with an unescaped opening sign; rust data
+                Ok(Self {
+                    r#type: TokenType::UnescapedRustString,
+                    raw: value[2..].to_string(),
+                    html: String::new(),
+                    indent,
+                    line,
+                    selector: None,
+                    arguments: None,
+                })
             }
             '@' => {
                 // begins with @; raw html
                 let mut raw = String::new();
 
-                while let Some(char) = chars.next() {
+                for char in chars {
                     raw.push(char);
                 }
 
-                return Some(Self {
+                Ok(Self {
                     r#type: TokenType::Html,
                     raw: raw.clone(),
                     html: raw,
                     indent,
                     line,
                     selector: None,
-                });
+                    arguments: None,
+                })
             }
             _ => {
                 // no recognizable starting character; raw data
                 // let sanitizer = Builder::new();
-                return Some(Self {
+                Ok(Self {
                     r#type: TokenType::Raw,
                     raw: value.clone(),
                     // html: sanitizer.clean(&value).to_string(),
@@ -225,17 +419,51 @@ impl Token {
                     indent,
                     line,
                     selector: None,
-                });
+                    arguments: None,
+                })
             }
         }
     }
 }
 
+/// Read the arguments of a selector (`%include:other(c = 3)`), after the opening `(`.
+///
+/// Returns [`None`] if the arguments are never closed.
+fn read_arguments(chars: &mut std::str::Chars) -> Option<String> {
+    let mut arguments = String::new();
+    let mut depth: i32 = 0;
+    let mut in_string: bool = false;
+
+    while let Some(char) = chars.next() {
+        if in_string {
+            if char == '\\' {
+                arguments.push(char);
+                arguments.extend(chars.next());
+                continue;
+            } else if char == '"' {
+                in_string = false;
+            }
+        } else {
+            match char {
+                '"' => in_string = true,
+                '(' => depth += 1,
+                ')' if depth == 0 => return Some(arguments),
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+
+        arguments.push(char);
+    }
+
+    None
+}
+
 /// Iterable version of [`Parser`]. Created through [`Parser::parse`].
 pub struct TokenStream(Parser);
 
 impl Iterator for TokenStream {
-    type Item = Token;
+    type Item = Result<Token>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next()
@@ -279,23 +507,21 @@ impl Parser {
     }
 
     /// Parse the next line in the given `input`
-    pub fn next(&mut self) -> Option<Token> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Result<Token>> {
         // get line
         self.1.line_number += 1;
-        let line = match self.0.get(self.1.line_number as usize) {
-            Some(l) => l,
-            None => return None,
-        };
+        let line = self.0.get(self.1.line_number as usize)?;
 
         if line.is_empty() {
-            return Some(Token::from_indent_ln(0, self.1.line_number));
+            return Some(Ok(Token::from_indent_ln(0, self.1.line_number)));
         }
 
         // get indent
         let mut indent: i32 = 0;
-        let mut chars = line.chars();
+        let chars = line.chars();
 
-        while let Some(char) = chars.next() {
+        for char in chars {
             if (char != ' ') & (char != '\t') {
                 break;
             }
@@ -304,6 +530,50 @@ impl Parser {
         }
 
         // parse token
-        Token::from_string(line.trim().to_owned(), indent, self.1.line_number)
+        Some(Token::from_string(
+            line.trim().to_owned(),
+            indent,
+            self.1.line_number,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Token {
+        Token::from_string(line.to_string(), 0, 0).unwrap()
+    }
+
+    #[test]
+    fn selector_arguments() {
+        let token = parse("%include:other(c = (1 + 2))");
+        assert_eq!(token.arguments.as_deref(), Some("c = (1 + 2)"));
+        assert_eq!(token.selector.unwrap().tag, "include:other");
+    }
+
+    #[test]
+    fn parentheses_in_attributes() {
+        let token = parse(r#"%button[onclick="go(1)"]'Go"#);
+        assert_eq!(token.arguments, None);
+        assert_eq!(token.html, r#"<button onclick="go(1)">Go</button>"#);
+
+        let token = parse(r#"%div[style="width: calc(100% - 2px)"]"#);
+        assert_eq!(token.arguments, None);
+    }
+
+    #[test]
+    fn quotes_in_attributes() {
+        let token = parse("%a[title='~(x)'][href=\"a'b\"]'text");
+        assert_eq!(token.arguments, None);
+        assert_eq!(token.indent, 0);
+        assert_eq!(token.html, "<a title='~(x)' href=\"a'b\">text</a>");
+    }
+
+    #[test]
+    fn unclosed_arguments() {
+        let error = Token::from_string("%call:card(a = 1".to_string(), 0, 0).unwrap_err();
+        assert_eq!(error.message, "unclosed arguments");
     }
 }
@@ -1,3 +1,5 @@
+use crate::error::{Error, Result};
+
 /// The extracted data from the given [`Selector].
 #[derive(Clone, Debug)]
 pub struct SelectorState {
@@ -53,6 +55,9 @@ impl SelectorState {
     }
 
     /// Render state to HTML.
+    ///
+    /// Unquoted attribute values (`[title={t}]`) are quoted, so interpolated values can't
+    /// end them.
     pub fn render(self) -> String {
         let mut class_string = String::new();
         let mut id_string = String::new();
@@ -74,7 +79,12 @@ impl SelectorState {
 
         if let Some(attributes) = self.attributes {
             for attribute in attributes {
-                attributes_string.push_str(&format!(" {attribute}"));
+                match attribute.split_once('=') {
+                    Some((name, value)) if !value.starts_with(['"', '\'']) => {
+                        attributes_string.push_str(&format!(" {name}=\"{value}\""))
+                    }
+                    _ => attributes_string.push_str(&format!(" {attribute}")),
+                }
             }
         }
 
@@ -101,7 +111,9 @@ impl Selector {
     }
 
     /// Begin parsing the selector.
-    pub fn parse(self) -> SelectorState {
+    ///
+    /// Columns in returned errors are relative to the start of the selector.
+    pub fn parse(self) -> Result<SelectorState> {
         let mut state = SelectorState {
             tag: String::new(),
             classes: None,
@@ -110,11 +122,27 @@ impl Selector {
         };
 
         // parse
-        let mut chars = self.0.chars();
+        let chars = self.0.chars();
         let mut mode: ParserMode = ParserMode::None;
         let mut buffer: String = String::new();
+        let mut attribute_start: usize = 0;
+        // the quote of the attribute value we're in
+        let mut quote: Option<char> = None;
+
+        for (column, char) in chars.enumerate() {
+            if mode == ParserMode::Attribute {
+                // quoted attribute values are taken as they are
+                match (quote, char) {
+                    (Some(open), _) if char == open => quote = None,
+                    (None, '"' | '\'') => quote = Some(char),
+                    (None, _) => {}
+                    (Some(_), _) => {
+                        buffer.push(char);
+                        continue;
+                    }
+                }
+            }
 
-        while let Some(char) = chars.next() {
             match char {
                 '.' => {
                     if mode == ParserMode::Attribute {
@@ -138,9 +166,18 @@ impl Selector {
                 }
                 '[' => {
                     buffer = state.try_save(mode, buffer.clone());
-                    mode = ParserMode::Attribute
+                    mode = ParserMode::Attribute;
+                    attribute_start = column;
                 }
                 ']' => {
+                    if mode != ParserMode::Attribute {
+                        return Err(Error::new(
+                            "unexpected `]` in selector",
+                            "no attribute was opened",
+                        )
+                        .at(1, column + 1));
+                    }
+
                     buffer = state.try_save(mode, buffer.clone());
                     mode = ParserMode::None
                 }
@@ -148,8 +185,66 @@ impl Selector {
             }
         }
 
+        if mode == ParserMode::Attribute {
+            return Err(
+                Error::new("unclosed attribute in selector", "expected a closing `]`")
+                    .at(1, attribute_start + 1),
+            );
+        }
+
         // return
         state.try_save(mode, buffer.clone()); // one last save to catch the ending stuff
-        state
+
+        if state.tag.is_empty() {
+            return Err(Error::new(
+                "missing tag name in selector",
+                "expected an element name after `%`",
+            ));
+        }
+
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(selector: &str) -> String {
+        Selector::new(selector.to_string())
+            .parse()
+            .unwrap()
+            .render()
+    }
+
+    #[test]
+    fn render_selector() {
+        assert_eq!(
+            render("a.link.active#home[href=\"/\"][hidden]"),
+            r#"<a class="link active " id="home" href="/" hidden>"#
+        );
+    }
+
+    #[test]
+    fn brackets_in_quoted_attributes() {
+        assert_eq!(render(r#"a[title="a]b"]"#), r#"<a title="a]b">"#);
+        assert_eq!(
+            render(r#"a[href="{v[0]}"][data-x='[]']"#),
+            r#"<a href="{v[0]}" data-x='[]'>"#
+        );
+        assert_eq!(render(r#"a[title="it's"]"#), r#"<a title="it's">"#);
+
+        let error = Selector::new(r#"a[title="a]"#.to_string())
+            .parse()
+            .unwrap_err();
+        assert_eq!(error.message, "unclosed attribute in selector");
+    }
+
+    #[test]
+    fn unquoted_attributes_are_quoted() {
+        assert_eq!(
+            render("a[title={t}][href='/']"),
+            r#"<a title="{t}" href='/'>"#
+        );
     }
 }
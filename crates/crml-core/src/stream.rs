@@ -0,0 +1,85 @@
+//! Streaming templates into an [`AsyncWrite`].
+//!
+//! Only available with the `async` feature. Templates render into a [`Stream`], which
+//! buffers output and writes it to the underlying writer at element boundaries.
+use std::fmt::{Result, Write};
+use std::future::poll_fn;
+use std::pin::Pin;
+
+pub use futures_io::AsyncWrite;
+
+/// A [`Write`] buffer which is flushed into an [`AsyncWrite`].
+///
+/// Like the adapter used by [`Template::render_into_io`](crate::Template::render_into_io),
+/// IO errors are kept so they can be returned once rendering stops.
+pub struct Stream<'a, W: AsyncWrite + Unpin + ?Sized> {
+    inner: &'a mut W,
+    buffer: String,
+    error: Option<std::io::Error>,
+}
+
+impl<'a, W: AsyncWrite + Unpin + ?Sized> Stream<'a, W> {
+    /// Create a new [`Stream`] into `inner`, buffering up to `capacity` bytes
+    /// without reallocating.
+    pub fn new(inner: &'a mut W, capacity: usize) -> Self {
+        Self {
+            inner,
+            buffer: String::with_capacity(capacity),
+            error: None,
+        }
+    }
+
+    /// Write everything buffered so far into the underlying writer.
+    pub async fn flush(&mut self) -> Result {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        match write_all(self.inner, self.buffer.as_bytes()).await {
+            Ok(()) => {
+                self.buffer.clear();
+                Ok(())
+            }
+            Err(e) => {
+                self.error = Some(e);
+                Err(std::fmt::Error)
+            }
+        }
+    }
+
+    /// Finish a render which ended with `result`, flushing anything left in the buffer.
+    pub async fn finish(mut self, result: Result) -> std::io::Result<()> {
+        if result.and(self.flush().await).is_err() {
+            return Err(self
+                .error
+                .unwrap_or_else(|| std::io::Error::other("failed to render template")));
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin + ?Sized> Write for Stream<'_, W> {
+    fn write_str(&mut self, s: &str) -> Result {
+        self.buffer.push_str(s);
+        Ok(())
+    }
+}
+
+/// Write all of `buf` into `out`, and flush it.
+pub async fn write_all<W: AsyncWrite + Unpin + ?Sized>(
+    out: &mut W,
+    mut buf: &[u8],
+) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        let written = poll_fn(|cx| Pin::new(&mut *out).poll_write(cx, buf)).await?;
+
+        if written == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+
+        buf = &buf[written..];
+    }
+
+    poll_fn(|cx| Pin::new(&mut *out).poll_flush(cx)).await
+}
@@ -0,0 +1,222 @@
+use crate::config::{Escape, Options, Whitespace};
+use proc_macro2::Span;
+use serde::Deserialize;
+use serde::de::IntoDeserializer;
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::{Ident, LitStr, Token};
+
+/// The names of all options of the `template` attribute.
+static OPTIONS: &[&str] = &["path", "source", "ext", "escape", "whitespace", "crate"];
+
+/// The arguments of the `template` attribute.
+///
+/// ```rust,ignore
+/// #[template("name")]
+/// #[template("name", ext = "html", escape = "none", whitespace = "trim")]
+/// #[template(path = "pages/index.crml")]
+/// #[template(path = "pages/index.html.crml", ext = "html.crml")]
+/// #[template(source = "%p'{a}")]
+/// #[template("name", crate = "my_crate::crml")]
+/// ```
+#[derive(Default)]
+pub struct TemplateArgs {
+    /// The name of the template file, resolved from the configured `root_dir`.
+    pub name: Option<LitStr>,
+    /// The path of the template file (with its extension), relative to the configured
+    /// `root_dir`.
+    pub path: Option<LitStr>,
+    /// The source of the template, given in the attribute itself.
+    pub source: Option<LitStr>,
+    /// The extension of the template file, overriding the configured `ext`.
+    ///
+    /// Templates given by `path` are named after their path without this extension.
+    pub ext: Option<LitStr>,
+    /// How interpolated values are escaped, overriding the configured `escape`.
+    pub escape: Option<Escape>,
+    /// How whitespace is rendered, overriding the configured `whitespace`.
+    pub whitespace: Option<Whitespace>,
+    /// The path of the `crml` crate, if it can't be found from `Cargo.toml`.
+    pub krate: Option<syn::Path>,
+}
+
+impl TemplateArgs {
+    /// The literal the template is given with; errors in the template are reported here.
+    ///
+    /// `None` if no template is given (like in `#[template]` on an enum whose variants all
+    /// have their own template).
+    pub fn template(&self) -> Option<&LitStr> {
+        [&self.name, &self.path, &self.source]
+            .into_iter()
+            .find_map(Option::as_ref)
+    }
+
+    /// The error for a template attribute which doesn't give a template at `span`.
+    pub fn missing(span: Span) -> syn::Error {
+        syn::Error::new(
+            span,
+            "expected the name of a template file (`\"name\"`), its path (`path = \"...\"`) or its source (`source = \"...\"`)",
+        )
+    }
+
+    /// Override the given `options` with the options given in the attribute.
+    pub fn options(&self, mut options: Options) -> Options {
+        if let Some(escape) = self.escape {
+            options.escape = escape;
+        }
+
+        if let Some(whitespace) = self.whitespace {
+            options.whitespace = whitespace;
+        }
+
+        options
+    }
+}
+
+impl Parse for TemplateArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = Self::default();
+
+        if input.peek(LitStr) {
+            args.name = Some(input.parse()?);
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        let mut seen: Vec<String> = Vec::new();
+
+        while !input.is_empty() {
+            // `crate` is a keyword
+            let key = Ident::parse_any(input)?;
+            let name = key.to_string();
+
+            if !OPTIONS.contains(&name.as_str()) {
+                return Err(syn::Error::new(
+                    key.span(),
+                    format!(
+                        "unknown template option `{key}`, expected one of `{}`",
+                        OPTIONS.join("`, `")
+                    ),
+                ));
+            }
+
+            if seen.contains(&name) {
+                return Err(syn::Error::new(
+                    key.span(),
+                    format!("template option `{key}` is given more than once"),
+                ));
+            }
+
+            input.parse::<Token![=]>()?;
+            let value: LitStr = input.parse()?;
+
+            match name.as_str() {
+                "path" => args.path = Some(value),
+                "source" => args.source = Some(value),
+                "ext" => args.ext = Some(value),
+                "escape" => args.escape = Some(variant(&value)?),
+                "whitespace" => args.whitespace = Some(variant(&value)?),
+                "crate" => args.krate = Some(value.parse()?),
+                _ => unreachable!("checked above"),
+            }
+
+            seen.push(name);
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        let given: Vec<&LitStr> = [&args.name, &args.path, &args.source]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        if let [_, second, ..] = given.as_slice() {
+            return Err(syn::Error::new(
+                second.span(),
+                "a template can only have one of a file name, a `path` or a `source`",
+            ));
+        }
+
+        match args.ext {
+            Some(ref ext) if args.source.is_some() => Err(syn::Error::new(
+                ext.span(),
+                "`ext` can only be used with a template file (its name or `path`)",
+            )),
+            _ => Ok(args),
+        }
+    }
+}
+
+/// Parse the value of an option which is one of the variants of `T`.
+fn variant<T: for<'de> Deserialize<'de>>(value: &LitStr) -> syn::Result<T> {
+    T::deserialize(value.value().as_str().into_deserializer())
+        .map_err(|e: serde::de::value::Error| syn::Error::new(value.span(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> syn::Result<TemplateArgs> {
+        syn::parse_str(args)
+    }
+
+    fn error(args: &str) -> String {
+        match parse(args) {
+            Ok(_) => panic!("expected `{args}` to be invalid"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn name_and_options() {
+        let args = parse(
+            r#""pages/index", ext = "html", escape = "none", whitespace = "trim", crate = "a::crml""#,
+        )
+        .unwrap();
+
+        assert_eq!(args.name.unwrap().value(), "pages/index");
+        assert_eq!(args.ext.unwrap().value(), "html");
+        assert!(args.escape == Some(Escape::None));
+        assert!(args.whitespace == Some(Whitespace::Trim));
+
+        let krate = args.krate.unwrap();
+        assert_eq!(quote::quote!(#krate).to_string(), "a :: crml");
+    }
+
+    #[test]
+    fn path_with_ext() {
+        let args = parse(r#"path = "pages/index.crml", ext = "crml""#).unwrap();
+        assert_eq!(args.path.unwrap().value(), "pages/index.crml");
+        assert_eq!(args.ext.unwrap().value(), "crml");
+    }
+
+    #[test]
+    fn source() {
+        let args = parse(r#"source = "%p'{a}""#).unwrap();
+        assert_eq!(args.template().unwrap().value(), "%p'{a}");
+        assert!(parse("").unwrap().template().is_none());
+    }
+
+    #[test]
+    fn invalid_args() {
+        assert_eq!(
+            error(r#"source = "%p", ext = "html""#),
+            "`ext` can only be used with a template file (its name or `path`)"
+        );
+        assert_eq!(
+            error(r#""index", path = "index.crml""#),
+            "a template can only have one of a file name, a `path` or a `source`"
+        );
+        assert_eq!(
+            error(r#""index", ext = "a", ext = "b""#),
+            "template option `ext` is given more than once"
+        );
+        assert!(error(r#""index", extension = "html""#).starts_with("unknown template option"));
+        assert!(error(r#""index", escape = "xml""#).contains("unknown variant"));
+    }
+}
@@ -0,0 +1,374 @@
+use serde::{Serialize, Deserialize};
+use pathbufd::PathBufD;
+use crml_core::{Error, Result};
+
+use std::fs::{read_to_string, File};
+use std::path::{Path, PathBuf};
+
+/// The name of the configuration file.
+static CONFIG_FILE: &str = "crml.json";
+
+/// The `crml.json` file, or the `[package.metadata.crml]` table of a `Cargo.toml`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Config {
+    /// The root directory to load all templates from.
+    ///
+    /// Relative paths are resolved from the directory the configuration was found in.
+    ///
+    /// # Example
+    /// ```json
+    /// {
+    ///     "root_dir": "./templates"
+    /// }
+    /// ```
+    ///
+    /// ```toml
+    /// [package.metadata.crml]
+    /// root_dir = "./templates"
+    /// ```
+    pub root_dir: PathBufD,
+    /// The extension of template files, `crml` by default.
+    ///
+    /// # Example
+    /// ```json
+    /// {
+    ///     "root_dir": "./templates",
+    ///     "ext": "html.crml"
+    /// }
+    /// ```
+    #[serde(default = "default_ext")]
+    pub ext: String,
+    /// The default options of all templates.
+    #[serde(flatten)]
+    pub options: Options,
+    /// The file this configuration was read from.
+    #[serde(skip)]
+    pub source: PathBufD,
+}
+
+/// Options used to generate a template.
+///
+/// Set for all templates in the configuration, and for a single template with the
+/// `template` attribute.
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct Options {
+    /// How interpolated values are escaped.
+    pub escape: Escape,
+    /// How whitespace in templates is rendered.
+    pub whitespace: Whitespace,
+}
+
+/// How interpolated values (`{a}` and `= a`) are escaped.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Escape {
+    /// Escape values as HTML.
+    #[default]
+    Html,
+    /// Write values as they are, like `{!= a}` and `!= a`.
+    None,
+}
+
+/// How whitespace in templates is rendered.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Whitespace {
+    /// Keep blank lines and the whitespace around text.
+    #[default]
+    Preserve,
+    /// Remove blank lines and the whitespace around text.
+    ///
+    /// Whitespace sensitive elements (like `pre`) are left alone.
+    Trim,
+}
+
+/// The parts of a `Cargo.toml` we need to find a configuration in.
+#[derive(Deserialize)]
+struct Manifest {
+    package: Option<Metadata>,
+    workspace: Option<Metadata>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    metadata: Option<CrmlMetadata>,
+}
+
+#[derive(Deserialize)]
+struct CrmlMetadata {
+    crml: Option<Config>,
+}
+
+impl Config {
+    /// Find the configuration for the crate currently being compiled.
+    ///
+    /// Starting at `CARGO_MANIFEST_DIR`, every directory up to the workspace root is
+    /// checked for a `crml.json` file, or a `Cargo.toml` with a `[package.metadata.crml]`
+    /// (or `[workspace.metadata.crml]`) table. The first configuration found is used.
+    ///
+    /// Crates which aren't part of a workspace only have their own directory checked.
+    pub fn discover() -> Result<Self> {
+        let start: PathBufD = match std::env::var("CARGO_MANIFEST_DIR") {
+            Ok(dir) => PathBuf::from(dir).into(),
+            Err(_) => PathBufD::current(),
+        };
+
+        Self::discover_from(start.as_path())
+    }
+
+    /// Find the configuration for the crate in the `start` directory, like
+    /// [`discover`](Self::discover).
+    fn discover_from(start: &Path) -> Result<Self> {
+        let root = Self::workspace_root(start);
+
+        for current in start.ancestors() {
+            let json = current.join(CONFIG_FILE);
+
+            if json.is_file() {
+                return Self::from_json(&json);
+            }
+
+            let manifest = current.join("Cargo.toml");
+
+            if manifest.is_file() {
+                let parsed = Self::read_manifest(&manifest)?;
+
+                for metadata in [parsed.package, parsed.workspace].into_iter().flatten() {
+                    if let Some(mut config) = metadata.metadata.and_then(|m| m.crml) {
+                        config.resolve(current, &manifest);
+                        return Ok(config);
+                    }
+                }
+            }
+
+            if current == root {
+                break;
+            }
+        }
+
+        Err(Error::new(
+            "failed to find configuration",
+            format!(
+                "expected a `{CONFIG_FILE}` file or a `[package.metadata.crml]` table in `Cargo.toml` (searched from `{}`)",
+                start.display()
+            ),
+        ))
+    }
+
+    /// The root of the workspace the crate in `dir` belongs to: the closest directory with
+    /// a `Cargo.toml` that has a `[workspace]` table, like cargo finds it.
+    ///
+    /// Crates which aren't part of a workspace are their own root.
+    fn workspace_root(dir: &Path) -> &Path {
+        dir.ancestors()
+            .find(|current| {
+                let manifest = current.join("Cargo.toml");
+                manifest.is_file()
+                    && Self::read_manifest(&manifest).is_ok_and(|parsed| parsed.workspace.is_some())
+            })
+            .unwrap_or(dir)
+    }
+
+    /// Read a configuration from a `crml.json` file.
+    fn from_json(path: &Path) -> Result<Self> {
+        let mut config = serde_json::from_str::<Config>(&read(path)?).map_err(|e| {
+            Error::new("failed to deserialize configuration", e.to_string())
+                .in_file(path.display().to_string())
+        })?;
+
+        config.resolve(path.parent().unwrap_or(Path::new("")), path);
+        Ok(config)
+    }
+
+    /// Read a `Cargo.toml` file.
+    fn read_manifest(path: &Path) -> Result<Manifest> {
+        toml::from_str::<Manifest>(&read(path)?).map_err(|e| {
+            Error::new("failed to deserialize configuration", e.to_string())
+                .in_file(path.display().to_string())
+        })
+    }
+
+    /// Resolve the paths of the configuration from the `dir` it was found in.
+    fn resolve(&mut self, dir: &Path, source: &Path) {
+        self.root_dir = dir.join(&self.root_dir).into();
+        self.source = source.to_path_buf().into();
+    }
+
+    /// Get the file name (relative to `root_dir`) of the template with the given `name`.
+    pub fn file(&self, name: &str) -> String {
+        format!("{name}.{}", self.ext)
+    }
+
+    /// Get the full path of the template `file`.
+    pub fn path(&self, file: &str) -> PathBufD {
+        self.root_dir.join(file)
+    }
+
+    /// Open the template `file`.
+    pub fn open(&self, file: &str) -> Result<File> {
+        File::open(self.path(file))
+            .map_err(|e| Error::new(format!("failed to open template `{file}`"), e.to_string()))
+    }
+}
+
+/// The default extension of template files.
+fn default_ext() -> String {
+    "crml".to_string()
+}
+
+/// Read a configuration file to a string.
+fn read(path: &Path) -> Result<String> {
+    read_to_string(path).map_err(|e| {
+        Error::new("failed to read configuration", e.to_string())
+            .in_file(path.display().to_string())
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A temporary directory tree, removed once dropped.
+    pub(crate) struct Tree(PathBuf);
+
+    impl Tree {
+        /// Create a tree named `name` with the given `files` (path and content).
+        pub(crate) fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let root = std::env::temp_dir().join(format!("crml-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+
+            for (path, content) in files {
+                let path = root.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, content).unwrap();
+            }
+
+            Self(root)
+        }
+
+        pub(crate) fn path(&self, path: &str) -> PathBuf {
+            self.0.join(path)
+        }
+
+        pub(crate) fn discover(&self, start: &str) -> Result<Config> {
+            let start = self.path(start);
+            std::fs::create_dir_all(&start).unwrap();
+            Config::discover_from(&start)
+        }
+
+        fn error(&self, start: &str) -> Error {
+            match self.discover(start) {
+                Ok(_) => panic!("expected no configuration to be found from `{start}`"),
+                Err(e) => e,
+            }
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    static WORKSPACE: &str = "[workspace]\nmembers = [\"crates/*\"]\n";
+    static PACKAGE: &str = "[package]\nname = \"a\"\n";
+
+    #[test]
+    fn walks_up_to_the_workspace_root() {
+        let tree = Tree::new(
+            "walk",
+            &[
+                ("Cargo.toml", WORKSPACE),
+                ("crml.json", r#"{ "root_dir": "templates", "ext": "html" }"#),
+                ("crates/a/Cargo.toml", PACKAGE),
+            ],
+        );
+
+        let config = tree.discover("crates/a").unwrap();
+        assert_eq!(config.root_dir.as_path(), tree.path("templates"));
+        assert_eq!(config.source.as_path(), tree.path("crml.json"));
+        assert_eq!(config.ext, "html");
+    }
+
+    #[test]
+    fn stops_at_the_workspace_root() {
+        let tree = Tree::new(
+            "stop",
+            &[
+                ("crml.json", r#"{ "root_dir": "templates" }"#),
+                ("workspace/Cargo.toml", WORKSPACE),
+                ("workspace/crates/a/Cargo.toml", PACKAGE),
+            ],
+        );
+
+        let error = tree.error("workspace/crates/a");
+        assert_eq!(error.message, "failed to find configuration");
+        assert!(tree.discover("workspace").is_err());
+    }
+
+    #[test]
+    fn crates_outside_of_a_workspace_are_their_own_root() {
+        let tree = Tree::new(
+            "standalone",
+            &[
+                ("crml.json", r#"{ "root_dir": "templates" }"#),
+                ("a/Cargo.toml", PACKAGE),
+                ("b/Cargo.toml", PACKAGE),
+                ("b/crml.json", r#"{ "root_dir": "templates" }"#),
+            ],
+        );
+
+        assert!(tree.discover("a").is_err());
+
+        let config = tree.discover("b").unwrap();
+        assert_eq!(config.root_dir.as_path(), tree.path("b/templates"));
+    }
+
+    #[test]
+    fn package_metadata() {
+        let tree = Tree::new(
+            "package",
+            &[
+                ("Cargo.toml", WORKSPACE),
+                ("crml.json", r#"{ "root_dir": "shared" }"#),
+                (
+                    "crates/a/Cargo.toml",
+                    "[package]\nname = \"a\"\n\n[package.metadata.crml]\nroot_dir = \"templates\"\nescape = \"none\"\n",
+                ),
+            ],
+        );
+
+        // the closest configuration is used
+        let config = tree.discover("crates/a/src").unwrap();
+        assert_eq!(config.root_dir.as_path(), tree.path("crates/a/templates"));
+        assert_eq!(config.source.as_path(), tree.path("crates/a/Cargo.toml"));
+        assert_eq!(config.ext, "crml");
+        assert!(config.options.escape == Escape::None);
+    }
+
+    #[test]
+    fn workspace_metadata() {
+        let tree = Tree::new(
+            "workspace",
+            &[
+                (
+                    "Cargo.toml",
+                    "[workspace]\nmembers = [\"crates/*\"]\n\n[workspace.metadata.crml]\nroot_dir = \"templates\"\n",
+                ),
+                ("crates/a/Cargo.toml", PACKAGE),
+            ],
+        );
+
+        let config = tree.discover("crates/a").unwrap();
+        assert_eq!(config.root_dir.as_path(), tree.path("templates"));
+        assert_eq!(config.source.as_path(), tree.path("Cargo.toml"));
+    }
+
+    #[test]
+    fn invalid_configuration() {
+        let tree = Tree::new("invalid", &[("crml.json", "{}")]);
+        let error = tree.error("");
+        assert_eq!(error.message, "failed to deserialize configuration");
+    }
+}
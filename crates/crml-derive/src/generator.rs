@@ -1,22 +1,275 @@
-use crml_core::{TokenStream, TokenType, Parser};
-use std::{fs::File, io::Read};
+use crml_core::{Error, Result, Parser};
+use crate::config::{Config, Escape, Options, Whitespace};
+use crml_core::ast::{Element, Location, Node, VOID_ELEMENTS};
+use crml_core::escape::Context;
+use crml_core::interpolation::{segments, Segment};
+use proc_macro2::{Group, Span, TokenStream as TokenStream2, TokenTree};
+use quote::{quote, ToTokens};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
 
-static RAW_BLOCK_TAG_PREFIX: &str = "r:";
-static SLOT_BLOCK_TAG_PREFIX: &str = "s:";
+/// Elements which do not accept interpolations in their content.
+static WHITESPACE_SENSITIVE: &[&str] = &[
+    // these must be closed manually
+    "script", "style", "pre", "html", "body", "head", "form", "dialog",
+];
 
-/// Generate valid Rust from a given [`TokenStream`].
-pub struct Generator(TokenStream);
+/// The Rust generated for a template by [`Generator::generate`].
+pub struct Generated {
+    /// The body of the template's `render_into` function.
+    pub code: Code,
+    /// The number of bytes of static text in the template.
+    ///
+    /// Used as the initial capacity of rendered strings.
+    pub size_hint: usize,
+    /// The fields required by the template (and its bases) through `%requires`.
+    pub requires: Vec<Requirement>,
+}
+
+/// Generated Rust, along with the source written to `crml_dbg` for it.
+///
+/// The debug source has the template file and line of every Rust line from a template
+/// in a comment after it.
+#[derive(Clone, Default)]
+pub struct Code {
+    pub tokens: TokenStream2,
+    pub debug: String,
+}
+
+impl Code {
+    /// Build the code around the given `codes`, with `build` placing their tokens.
+    pub fn assemble(codes: &[&Code], build: impl Fn(Vec<TokenStream2>) -> TokenStream2) -> Self {
+        let tokens = build(codes.iter().map(|code| code.tokens.clone()).collect());
+
+        // the debug source is built around placeholders, replaced by the debug source
+        // of every code
+        let placeholders = (0..codes.len())
+            .map(|i| syn::Ident::new(&format!("{DEBUG_PLACEHOLDER}{i}_"), Span::call_site()));
+        let mut debug = build(placeholders.map(ToTokens::into_token_stream).collect()).to_string();
+
+        for (i, code) in codes.iter().enumerate() {
+            debug = debug.replacen(&format!("{DEBUG_PLACEHOLDER}{i}_"), &code.debug, 1);
+        }
+
+        Self { tokens, debug }
+    }
+}
+
+impl From<TokenStream2> for Code {
+    fn from(tokens: TokenStream2) -> Self {
+        let debug = tokens.to_string();
+        Self { tokens, debug }
+    }
+}
+
+/// A field required by a template through `%requires`, as parsed from the template.
+struct RequiredField {
+    ident: syn::Ident,
+    /// The type the field is bound as.
+    ty: TokenStream2,
+    location: Location,
+}
+
+/// A field required by a template through `%requires`.
+pub struct Requirement {
+    /// The name of the field.
+    pub field: String,
+    /// The error to report if the struct doesn't have the field.
+    pub error: Error,
+}
+
+/// The closures rendering the content the slots of a base template are filled with, by
+/// slot name.
+///
+/// Fills are rendered through closures defined in the scope of the template they were
+/// written in, so the Rust of the base template can't change what they refer to.
+type Slots = HashMap<String, syn::Ident>;
+
+/// Generate valid Rust from a given template tree.
+pub struct Generator {
+    /// The parsed template.
+    tree: Vec<Node>,
+    /// The name of the template being generated, used in errors.
+    name: String,
+    /// The name of the file errors in the template are shown in.
+    file: String,
+    /// The lines of the template, quoted in errors.
+    lines: Vec<String>,
+    /// The full path of the template file, or `None` for templates given as source.
+    path: Option<String>,
+    /// The string literal the template was given in, for templates given as source.
+    ///
+    /// Rust written in the template is located at its line in the literal, where the
+    /// compiler supports it.
+    literal: Option<proc_macro2::Literal>,
+    /// The configuration templates are loaded with.
+    ///
+    /// Templates given as source don't need a configuration until they use another template.
+    config: Option<Config>,
+    /// The options the template is generated with.
+    ///
+    /// Templates used by this template are generated with the same options.
+    options: Options,
+    /// The fields which are never escaped (`#[crml(escape = false)]`).
+    raw: HashSet<String>,
+    /// The path of the `crml` crate in the crate being compiled.
+    krate: syn::Path,
+    /// The span all Rust written in the template is located at.
+    ///
+    /// Rust errors in the template (like type errors) are reported here.
+    span: Span,
+    /// The templates this template is a base of, from the template being generated
+    /// down to the one filling this template's slots.
+    chain: Vec<String>,
+}
 
 impl Generator {
-    /// Create a new [`Generator`] from a [`File`].
-    pub fn from_file(mut file: File) -> Self {
+    /// Create a new [`Generator`] from the template file with the given `name`.
+    ///
+    /// Rust errors in the template are reported at the given `span`.
+    pub fn from_file(name: &str, config: &Config, span: Span) -> Result<Self> {
+        Self::from_path(name, config.file(name), config, span)
+    }
+
+    /// Create a new [`Generator`] named `name` from the template `file` (relative to the
+    /// configured `root_dir`).
+    ///
+    /// Rust errors in the template are reported at the given `span`.
+    pub fn from_path(name: &str, file: String, config: &Config, span: Span) -> Result<Self> {
         // read file
         let mut content = String::new();
-        file.read_to_string(&mut content)
-            .expect("failed to read file");
+        config
+            .open(&file)?
+            .read_to_string(&mut content)
+            .map_err(|e| Error::new("failed to read template file", e.to_string()))?;
+
+        let path = config.path(&file).to_string();
+        let mut generator = Self::from_source(name, file, content, span)?;
+        generator.path = Some(path);
+        generator.config = Some(config.clone());
+        generator.options = config.options;
+        Ok(generator)
+    }
+
+    /// Create a new [`Generator`] from the given template `source`.
+    ///
+    /// Errors in the template are shown as found in `file`, and Rust errors are reported
+    /// at the given `span`.
+    pub fn from_source(name: &str, file: String, source: String, span: Span) -> Result<Self> {
+        let mut generator = Self {
+            tree: Vec::new(),
+            name: name.to_string(),
+            file,
+            lines: source.split("\n").map(str::to_owned).collect(),
+            path: None,
+            literal: None,
+            config: None,
+            options: Options::default(),
+            raw: HashSet::new(),
+            krate: syn::parse_quote!(::crml),
+            span,
+            chain: Vec::new(),
+        };
+
+        generator.tree = Parser::new(source)
+            .tree()
+            .map_err(|e| generator.annotate(e))?;
+
+        Ok(generator)
+    }
+
+    /// Locate Rust errors at the lines of the string `literal` the template is given in.
+    pub fn with_literal(mut self, literal: proc_macro2::Literal) -> Self {
+        self.literal = Some(literal);
+        self
+    }
+
+    /// Use the given `config` to load the templates used by this template.
+    pub fn with_config(mut self, config: Option<Config>) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Generate the template with the given `options`.
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Refer to the `crml` crate with the given `path` in the generated code.
+    pub fn with_crate(mut self, path: syn::Path) -> Self {
+        self.krate = path;
+        self
+    }
+
+    /// Never escape the given `fields` when they are interpolated or pushed.
+    pub fn with_raw(mut self, fields: impl IntoIterator<Item = String>) -> Self {
+        self.raw = fields.into_iter().collect();
+        self
+    }
+
+    /// The name of the template file being generated.
+    fn file_name(&self) -> String {
+        self.file.clone()
+    }
+
+    /// The span Rust written at `location` in the template is reported at.
+    ///
+    /// This is the location's line in the string literal the template was given in, if it
+    /// can be found. Otherwise, it's the template's [`span`](Self::span).
+    fn span_of(&self, location: Location) -> Span {
+        self.literal
+            .as_ref()
+            .and_then(|literal| {
+                let repr = literal.to_string();
+                let (start, end) = line_range(&repr, location.line)?;
+                literal.subspan((start + location.column - 1).min(end)..end)
+            })
+            .unwrap_or(self.span)
+    }
 
-        // return
-        Self(Parser::new(content).parse())
+    /// Push the Rust `code` written at `location` in this template.
+    fn code(&self, out: &mut Output, code: &str, location: Location) {
+        out.push_code(code, location, self.file_name(), self.span_of(location));
+    }
+
+    /// If this is the template file with the given `name`.
+    ///
+    /// Templates given as source can't be used by name.
+    fn is(&self, name: &str) -> bool {
+        self.path.is_some() && self.name == name
+    }
+
+    /// Read and parse the template file with the given `name`.
+    fn open(&self, name: &str) -> Result<Generator> {
+        let config = match self.config {
+            Some(ref config) => config.clone(),
+            None => Config::discover()?,
+        };
+
+        Generator::from_file(name, &config, self.span).map(|generator| {
+            // the options of the template being generated apply to everything it renders
+            generator
+                .with_options(self.options)
+                .with_raw(self.raw.iter().cloned())
+                .with_crate(self.krate.clone())
+        })
+    }
+
+    /// Attach this template's file and source line to an `error` found in it.
+    ///
+    /// Errors which already belong to another template are left alone.
+    fn annotate(&self, error: Error) -> Error {
+        let error = error.in_file(self.file_name());
+
+        if error.file.as_deref() != Some(&self.file_name()) {
+            return error;
+        }
+
+        match self.lines.get(error.line.saturating_sub(1)) {
+            Some(line) => error.with_source(line),
+            None => error,
+        }
     }
 
     /// Generate valid Rust from the given `input`.
@@ -25,14 +278,14 @@ impl Generator {
     /// This type is imported from `crate::crml::data::*`. That module should export all
     /// types which are going to be used as page data.
     ///
-    /// The example below shows you how to use the data given from calling `consume` with
+    /// The example below shows you how to use the data given from calling `generate` with
     /// the name of `test_template` and the `props_type` of `TestProps`.
     ///
     /// After building all templates, the `src/crml/mod.rs` file should be created
     /// with the combined contents of all functions, as well as the `use super::data::*` line
     /// in order to satisfy all type requirements.
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// //! main.rs
     /// mod crml;
     ///
@@ -47,7 +300,7 @@ impl Generator {
     /// }
     /// ```
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// //! crml/mod.rs
     /// // @generated crml build
     /// use super::data::*;
@@ -56,172 +309,1459 @@ impl Generator {
     /// }
     /// ```
     ///
-    /// ```rust
+    /// ```rust,ignore
     /// //! crml/data.rs - this should be written before building crml templates
     /// pub use crate::TestProps;
     /// ```
-    pub fn consume(mut self) -> String {
-        let mut out = format!("let mut crml_rendered = String::new();\nlet mut crml_templ_stack: Vec<String> = Vec::new();\n").to_string();
-        let mut last_tags: Vec<String> = Vec::new();
+    ///
+    /// If `streaming` is true, the output is flushed after every element, and the generated
+    /// code must run in an async function where `crml_rendered` is a `crml::stream::Stream`.
+    pub fn generate(&self, streaming: bool) -> Result<Generated> {
+        let mut out = Output::new(self.span, streaming, self.options, &self.krate);
+        let krate = &self.krate;
+        let mut raw: Vec<&String> = self.raw.iter().collect();
+        raw.sort();
 
-        let whitespace_sensitive = &[
-            // these must be closed manually
-            "script", "style", "pre", "html", "body", "head", "form", "dialog",
-        ];
+        // trusted fields are never escaped, but values computed from them are
+        for field in raw {
+            let ident = syn::Ident::new(field, self.span);
+            out.push(quote! { let #ident = #krate::escape::Raw(#ident); });
+        }
 
-        while let Some(mut token) = self.0.next() {
-            let mut last_tag = last_tags.last().unwrap_or(&String::new()).to_owned();
+        self.body(&mut out, &Slots::new(), true)?;
 
-            if last_tag.starts_with(RAW_BLOCK_TAG_PREFIX) {
-                // everything is raw html within raw block
-                token.r#type = TokenType::Html;
-                token.raw = format!(" {}", token.raw);
-                token.html = token.raw.clone();
+        let size_hint = out.size_hint;
+        let requires = std::mem::take(&mut out.requires);
+        let body = out.finish().map_err(|e| self.annotate(e))?;
+        let code = Code::assemble(&[&body], |tokens| {
+            let body = &tokens[0];
+            quote! {
+                #body
+                Ok(())
             }
+        });
 
-            if (token.r#type == TokenType::Selector) && (token.raw == "end") {
-                // automatically close previous element
-                out.push_str(&format!(
-                    "crml_rendered.push_str(&format!(\"</{last_tag}>\"));\n"
-                ));
+        Ok(Generated {
+            code,
+            size_hint,
+            requires,
+        })
+    }
 
-                last_tags.pop();
+    /// Push the Rust which renders the full template to `out`.
+    ///
+    /// If `bind` is true, the fields required by the template are bound from the struct
+    /// being rendered. Included templates have their fields bound from their arguments.
+    fn body(&self, out: &mut Output, slots: &Slots, bind: bool) -> Result<()> {
+        // rebuild when the template changes
+        if let Some(ref path) = self.path {
+            out.push(quote! { const _: &[u8] = include_bytes!(#path); });
+        }
+
+        if bind {
+            self.bind_fields(out).map_err(|e| self.annotate(e))?;
+        }
+
+        for node in &self.tree {
+            self.node(out, node, true, slots)
+                .map_err(|e| self.annotate(e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the fields required by the `%requires` lines of this template.
+    ///
+    /// Fields are bound by reference: `title: &str` binds `title` as a `&str`, and
+    /// `count: usize` binds `count` as a `&usize`.
+    fn required_fields(&self) -> Result<Vec<RequiredField>> {
+        let mut fields = Vec::new();
+
+        for node in &self.tree {
+            let Node::Requires { params, location } = node else {
                 continue;
+            };
+
+            let signature = syn::parse_str::<syn::Signature>(&format!("fn requires({params})"))
+                .map_err(|e| location.error("invalid `%requires` fields", e.to_string()))?;
+
+            for input in signature.inputs {
+                let syn::FnArg::Typed(syn::PatType { pat, ty, .. }) = input else {
+                    return Err(location
+                        .error("invalid `%requires` fields", "expected `name: Type` fields"));
+                };
+
+                let syn::Pat::Ident(ref ident) = *pat else {
+                    return Err(location.error(
+                        "invalid `%requires` fields",
+                        "expected a field name before `:`",
+                    ));
+                };
+
+                fields.push(RequiredField {
+                    ident: ident.ident.clone(),
+                    ty: match *ty {
+                        syn::Type::Reference(_) => ty.to_token_stream(),
+                        _ => quote! { &#ty },
+                    },
+                    location: *location,
+                });
             }
+        }
 
-            match token.r#type {
-                TokenType::RustString => {
-                    if !token.raw.ends_with("{") && token.raw != "}" {
-                        token.raw += ";";
-                    }
+        Ok(fields)
+    }
+
+    /// Bind the fields required by this template from the struct (or enum variant) being
+    /// rendered.
+    ///
+    /// The fields are already bound by reference under their own names, so they are only
+    /// rebound with the types the template expects. Trusted fields stay wrapped in `Raw`.
+    fn bind_fields(&self, out: &mut Output) -> Result<()> {
+        let krate = &self.krate;
+
+        for RequiredField {
+            ident,
+            ty,
+            location,
+        } in self.required_fields()?
+        {
+            let span = self.span_of(location);
+            out.push(match self.raw.contains(&ident.to_string()) {
+                true => respan(
+                    quote! { let #ident = #krate::escape::Raw::<#ty>(#ident.0); },
+                    span,
+                ),
+                false => respan(quote! { let #ident: #ty = #ident; }, span),
+            });
+            out.requires.push(Requirement {
+                field: ident.to_string(),
+                error: self.annotate(location.error(
+                    format!("template `{}` requires a field `{ident}`", self.name),
+                    format!("expected a `{ident}` field on the template's struct or variant"),
+                )),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Push the Rust which renders the `template` included at `location` in place, with
+    /// the given `arguments` (`c = 3, d = "text"`) as the fields it requires.
+    ///
+    /// Every included template is generated once, as a function taking the fields it
+    /// requires, so it can't use anything else from the including template.
+    fn include(
+        &self,
+        out: &mut Output,
+        template: &str,
+        arguments: &str,
+        location: Location,
+    ) -> Result<()> {
+        let partial = self.load(template, location, "includes")?;
+        let fields = partial.required_fields().map_err(|e| partial.annotate(e))?;
+        let names: Vec<String> = fields.iter().map(|field| field.ident.to_string()).collect();
+        let values = self.arguments(arguments, &names, &format!("`{template}.crml`"), location)?;
+        let (function, new) = out.function(Function::Include(partial.file_name()));
+        let ident = syn::Ident::new(&function, self.span);
+
+        if new {
+            // generate the partial's function, which only has access to its fields
+            let mut body = Output::new(self.span, false, partial.options, &partial.krate);
+            body.functions = std::mem::take(&mut out.functions);
+
+            partial
+                .body(&mut body, &Slots::new(), false)
+                .map_err(|e| partial.annotate(e))?;
+
+            out.functions = std::mem::take(&mut body.functions);
+            out.items.append(&mut body.items);
 
-                    out.push_str(&format!("{}//line: {}\n", token.raw, token.line));
+            let body = body.finish().map_err(|e| partial.annotate(e))?;
+            let inputs: Vec<TokenStream2> = fields
+                .iter()
+                .map(|RequiredField { ident, ty, .. }| respan(quote! { #ident: #ty }, self.span))
+                .collect();
+
+            out.items.push(Code::assemble(&[&body], |tokens| {
+                let body = &tokens[0];
+                quote! {
+                    #[allow(unused_variables)]
+                    fn #ident<W: ::core::fmt::Write + ?Sized>(
+                        crml_rendered: &mut W,
+                        #(#inputs,)*
+                    ) -> ::core::fmt::Result {
+                        #body
+                        Ok(())
+                    }
                 }
-                TokenType::PushedRustString => {
-                    out.push_str(&format!(
-                        "crml_rendered.push_str(&{});//line: {}\n",
-                        token.raw, token.line
-                    ));
+            }));
+        }
+
+        out.push(quote! { #ident(crml_rendered, #(&(#values),)*)?; });
+        Ok(())
+    }
+
+    /// Parse the `arguments` given to `target` (`c = 3, d = "text"`), returning their
+    /// values in the order of the expected `names`.
+    fn arguments(
+        &self,
+        arguments: &str,
+        names: &[String],
+        target: &str,
+        location: Location,
+    ) -> Result<Vec<TokenStream2>> {
+        let arguments = parse_arguments(arguments, self.span_of(location))
+            .map_err(|e| location.error("invalid arguments", e.to_string()))?;
+
+        for (i, (name, _)) in arguments.iter().enumerate() {
+            if !names.contains(name) {
+                return Err(location.error(
+                    format!("unexpected argument `{name}`"),
+                    format!("{target} doesn't take a `{name}` argument"),
+                ));
+            }
+
+            if arguments[..i].iter().any(|(other, _)| other == name) {
+                return Err(location.error(
+                    format!("argument `{name}` is given more than once"),
+                    "every argument can only be given once",
+                ));
+            }
+        }
+
+        names
+            .iter()
+            .map(|expected| {
+                arguments
+                    .iter()
+                    .find(|(name, _)| name == expected)
+                    .map(|(_, value)| value.clone())
+                    .ok_or_else(|| {
+                        location.error(
+                            format!("missing argument `{expected}`"),
+                            format!("{target} requires a `{expected}` argument"),
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Get the parameters, content and location of the component with the given `name`.
+    fn component(&self, name: &str) -> Option<(&str, &[Node], Location)> {
+        self.tree.iter().find_map(|node| match node {
+            Node::Component {
+                name: component,
+                params,
+                children,
+                location,
+            } if component == name => Some((params.as_str(), children.as_slice(), *location)),
+            _ => None,
+        })
+    }
+
+    /// Push the Rust which calls the `component` (from `template`, or this template) at
+    /// `location`, with the given `arguments` and `children`.
+    ///
+    /// Every component is generated once, as a function taking a closure which renders
+    /// the children of the call.
+    #[allow(clippy::too_many_arguments)]
+    fn call(
+        &self,
+        out: &mut Output,
+        template: Option<&str>,
+        component: &str,
+        arguments: &str,
+        children: &[Node],
+        location: Location,
+        slots: &Slots,
+    ) -> Result<()> {
+        let loaded = match template {
+            Some(template) if !self.is(template) => {
+                Some(self.open(template).map_err(|e| match e.file {
+                    // the file could not be read, point at the call
+                    None => self.error(e, location),
+                    Some(_) => e,
+                })?)
+            }
+            _ => None,
+        };
+        let generator = loaded.as_ref().unwrap_or(self);
+
+        let Some((params, nodes, defined)) = generator.component(component) else {
+            return Err(location.error(
+                format!("no component `{component}` in `{}`", generator.file_name()),
+                format!("expected a `%component:{component}(...)` line"),
+            ));
+        };
+
+        let inputs = syn::parse_str::<syn::Signature>(&format!("fn component({params})"))
+            .map_err(|e| {
+                generator.annotate(defined.error("invalid component parameters", e.to_string()))
+            })?
+            .inputs;
+
+        let mut names = Vec::new();
+
+        for input in &inputs {
+            match input {
+                syn::FnArg::Typed(syn::PatType { pat, .. })
+                    if let syn::Pat::Ident(ident) = pat.as_ref() =>
+                {
+                    names.push(ident.ident.to_string())
                 }
                 _ => {
-                    if token.raw == " " {
-                        continue;
+                    return Err(generator.annotate(defined.error(
+                        "invalid component parameters",
+                        "expected `name: Type` parameters",
+                    )));
+                }
+            }
+        }
+
+        let target = format!("component `{component}`");
+        let values = self.arguments(arguments, &names, &target, location)?;
+        let (function, new) = out.function(Function::Component(
+            generator.file_name(),
+            component.to_string(),
+        ));
+
+        if new {
+            // generate the component's function, which only has access to its parameters
+            let mut body = Output::new(self.span, false, generator.options, &generator.krate);
+            body.functions = std::mem::take(&mut out.functions);
+
+            // rebuild when the component's template changes
+            if let Some(ref path) = loaded.as_ref().and_then(|loaded| loaded.path.clone()) {
+                body.push(quote! { const _: &[u8] = include_bytes!(#path); });
+            }
+
+            for node in nodes {
+                generator
+                    .node(&mut body, node, true, &Slots::new())
+                    .map_err(|e| generator.annotate(e))?;
+            }
+
+            out.functions = std::mem::take(&mut body.functions);
+            out.items.append(&mut body.items);
+
+            let body = body.finish().map_err(|e| generator.annotate(e))?;
+            let ident = syn::Ident::new(&function, self.span);
+            let span = generator.span_of(defined);
+            let inputs: Vec<TokenStream2> = inputs
+                .iter()
+                .map(|input| respan(input.to_token_stream(), span))
+                .collect();
+
+            out.items.push(Code::assemble(&[&body], |tokens| {
+                let body = &tokens[0];
+                quote! {
+                    #[allow(unused_variables)]
+                    fn #ident<W: ::core::fmt::Write + ?Sized>(
+                        crml_rendered: &mut W,
+                        #(#inputs,)*
+                        crml_children: &mut dyn FnMut(&mut W) -> ::core::fmt::Result,
+                    ) -> ::core::fmt::Result {
+                        #body
+                        Ok(())
                     }
+                }
+            }));
+        }
 
-                    if token.raw == "\n" {
-                        out.push_str(&format!("crml_rendered.push_str(\"\\n\");\n"));
-                        continue;
+        // the arguments are evaluated before the children closure borrows anything
+        self.code(out, "{", location);
+        out.push(respan(
+            quote! { let crml_arguments = (#(#values,)*); },
+            self.span_of(location),
+        ));
+
+        let arguments: String = (0..names.len())
+            .map(|i| format!("crml_arguments.{i}, "))
+            .collect();
+
+        if children.is_empty() {
+            self.code(
+                out,
+                &format!("{function}(crml_rendered, {arguments}&mut |_| Ok(()))?;"),
+                location,
+            );
+        } else {
+            self.code(
+                out,
+                &format!("{function}(crml_rendered, {arguments}&mut |crml_rendered| {{"),
+                location,
+            );
+
+            // the children are rendered in a closure, where the output can't be flushed
+            let streaming = std::mem::replace(&mut out.streaming, false);
+
+            for child in children {
+                self.node(out, child, true, slots)?;
+            }
+
+            out.streaming = streaming;
+            self.code(out, "Ok(())", location);
+            self.code(out, "})?;", location);
+        }
+
+        self.code(out, "}", location);
+        Ok(())
+    }
+
+    /// Push the Rust which renders the given `node` to `out`.
+    ///
+    /// `interpolate` is false when the node's parent element is whitespace sensitive.
+    fn node(&self, out: &mut Output, node: &Node, interpolate: bool, slots: &Slots) -> Result<()> {
+        match node {
+            Node::Element(element) => self.element(out, element, slots)?,
+            Node::Text { text, location } => {
+                // whitespace sensitive elements are never trimmed
+                let trim = interpolate && out.options.whitespace == Whitespace::Trim;
+
+                if text == "\n" {
+                    if !trim {
+                        out.push_str("\n");
                     }
 
-                    if let Some(selector) = token.selector {
-                        if !selector.tag.starts_with("-") {
-                            last_tags.push(selector.tag.clone());
-                            last_tag = selector.tag.clone();
-
-                            if selector.tag == "slot" {
-                                // don't render <slot /> elements,
-                                // they should be literally insertted into the rust
-                                // in order to be replaced later
-                                out.push_str(&format!(
-                                    "crml_rendered.push_str(\"<slot {}/>\");\n",
-                                    selector
-                                        .attributes
-                                        .unwrap()
-                                        .get(0)
-                                        .unwrap()
-                                        .replace("\"", "\\\"")
-                                ));
-                                last_tags.pop();
-                                continue;
-                            }
-
-                            if selector.tag.starts_with(RAW_BLOCK_TAG_PREFIX) {
-                                // don't actually render this!
-                                continue;
-                            }
-
-                            if selector.tag.starts_with(SLOT_BLOCK_TAG_PREFIX) {
-                                // this is a slot for accepting another template as a base
-                                let classes = selector.classes.unwrap();
-
-                                let file_name = selector.tag.replace("s:", "");
-                                let name = classes.get(0).unwrap();
-
-                                // read file
-                                let generated =
-                                    Generator::from_file(crate::get_file(&file_name)).consume();
-
-                                // push block
-                                // in this block, we use the generated template and then rebuild
-                                // crml_rendered with both parts of the template surrounding the current
-                                // content that we have rendered
-                                out.push_str(&format!(
-                                    "let template_ = {{\n{generated}\n}};
-                                    let template_split_: Vec<&str> = template_.split(\"<slot name=\\\"{name}\\\"/>\").collect();
-                                    let template_half_0_ = template_split_.get(0).unwrap();
-                                    let template_half_1_ = template_split_.get(1).unwrap();
-                                    crml_rendered = format!(\"{{template_half_0_}}\n{{crml_rendered}}\n\");\n
-                                    crml_templ_stack.push(template_half_1_.to_string());\n"
-                                ));
-                                // we push the SECOND HALF of the template to crml_templ_stack
-                                // because that vector is all added (in order of push) to
-                                // the output string
-                                //
-                                // this is done so that everything added after is still
-                                // rendered into the correct template
-
-                                // continue
-                                continue;
-                            }
-                        } else {
-                            last_tags.pop();
-
-                            if selector
-                                .tag
-                                .starts_with(&format!("-{RAW_BLOCK_TAG_PREFIX}"))
-                            {
-                                // don't actually render this!
-                                continue;
-                            }
-                        }
+                    return Ok(());
+                }
+
+                let (text, offset) = match trim {
+                    true => {
+                        let trimmed = text.trim_start();
+                        (trimmed.trim_end(), text.len() - trimmed.len())
                     }
+                    false => (text.as_str(), 0),
+                };
+
+                // text is rendered as written, so the column can be kept
+                let span = self.span_of(*location);
+                out.push_html(text, interpolate, span).map_err(|e| {
+                    let column = e.column + location.column + offset - 1;
+                    e.at(location.line, column).in_file(self.file_name())
+                })?;
+            }
+            Node::RustStatement { code, location } => {
+                check_statement(code).map_err(|e| self.error(e, *location))?;
 
-                    if token.html.contains("</") && !last_tag.starts_with(RAW_BLOCK_TAG_PREFIX) {
-                        // token closed tag itself
-                        last_tags.pop();
+                let code = code.trim();
+
+                // lines opening or closing a block (like `match` arms) can't end with a `;`
+                if code.ends_with('{') || code == "}" {
+                    self.code(out, code, *location);
+                } else {
+                    self.code(out, &format!("{code};"), *location);
+                }
+            }
+            Node::RustExpr {
+                expr,
+                escape,
+                location,
+            } => {
+                let escape = *escape && out.escapes();
+                let expr = parse_expr(expr, self.span_of(*location))
+                    .map_err(|e| self.error(e, *location))?;
+                let krate = &self.krate;
+                out.push(if escape {
+                    quote! {
+                        #krate::escape::write_escaped(crml_rendered, &(#expr), #krate::escape::Context::Text)?;
                     }
+                } else {
+                    quote! {
+                        #krate::escape::write_raw(crml_rendered, &(#expr))?;
+                    }
+                });
+            }
+            Node::RawHtml { html, location } => {
+                out.push_html(html, interpolate, self.span_of(*location))
+                    .map_err(|e| self.error(e, *location))?;
+            }
+            Node::SlotDef { name, children, .. } => match slots.get(name) {
+                Some(fill) => out.push(quote! { #fill(crml_rendered)?; }),
+                None => {
+                    // not filled; render the default content
+                    for child in children {
+                        self.node(out, child, interpolate, slots)?;
+                    }
+                }
+            },
+            // bound once the template starts rendering
+            Node::Requires { .. } => {}
+            Node::Include {
+                template,
+                arguments,
+                location,
+            } => self.include(out, template, arguments, *location)?,
+            // generated as a function once it is called
+            Node::Component { .. } => {}
+            Node::Children { .. } => out.push(quote! { crml_children(crml_rendered)?; }),
+            Node::Call {
+                template,
+                component,
+                arguments,
+                children,
+                location,
+            } => self.call(
+                out,
+                template.as_deref(),
+                component,
+                arguments,
+                children,
+                *location,
+                slots,
+            )?,
+            Node::Fill { location, .. } => {
+                return Err(location.error(
+                    "unexpected fill block",
+                    "fill blocks must be at the top level, after a `%s:template` line",
+                ));
+            }
+            Node::SlotFill {
+                template,
+                slot,
+                children,
+                location,
+            } => self.compose(out, template, slot.as_deref(), children, *location, slots)?,
+        }
+
+        Ok(())
+    }
+
+    /// Push the Rust which renders the base `template` with its slots filled.
+    ///
+    /// With a `slot` name, all `children` fill that slot. Otherwise, the slots are filled
+    /// by the `%f:` blocks in `children`.
+    fn compose(
+        &self,
+        out: &mut Output,
+        template: &str,
+        slot: Option<&str>,
+        children: &[Node],
+        location: Location,
+        slots: &Slots,
+    ) -> Result<()> {
+        let base = self.load(template, location, "extends")?;
+        let names = base.slot_names(true).map_err(|e| base.annotate(e))?;
+        let mut fills = Slots::new();
 
-                    if whitespace_sensitive.contains(&last_tag.as_str()) {
-                        // whitespace sensitive blocks do not accept format params
-                        token.html = token.html.replace("{", "{{").replace("}", "}}");
+        // slots of the base which this template doesn't define itself can be
+        // filled by the templates using this one as a base
+        let own = self.slot_names(false)?;
+
+        for (name, fill) in slots {
+            if !own.contains(name) {
+                fills.insert(name.to_owned(), fill.clone());
+            }
+        }
+
+        if let Some(slot) = slot {
+            if !names.iter().any(|name| name == slot) {
+                return Err(location.error(
+                    format!("template `{template}` has no slot `{slot}`"),
+                    format!("expected a `%slot[name=\"{slot}\"]` in `{template}.crml`"),
+                ));
+            }
+
+            let fill = self.fill(out, children, location, slots)?;
+            fills.insert(slot.to_owned(), fill);
+            return base.body(out, &fills, true);
+        }
+
+        let mut blocks: Vec<(&str, &[Node], Location)> = Vec::new();
+
+        for child in children {
+            match child {
+                Node::Fill {
+                    slot,
+                    children,
+                    location,
+                } => {
+                    if !names.contains(slot) {
+                        return Err(location.error(
+                            format!("template `{template}` has no slot `{slot}`"),
+                            format!("expected a `%slot[name=\"{slot}\"]` in `{template}.crml`"),
+                        ));
                     }
 
-                    if token.raw.starts_with("-") {
-                        // replace our closing token (-) with the HTML one (/)
-                        token.html = token.html.replacen("-", "/", 1);
+                    if blocks.iter().any(|(filled, ..)| filled == slot) {
+                        return Err(location.error(
+                            format!("slot `{slot}` is filled more than once"),
+                            "every slot can only be filled by a single `%f:` block",
+                        ));
                     }
 
-                    out.push_str(&format!(
-                        "crml_rendered.push_str(&format!(\"{}\"));//line: {}\n",
-                        if last_tag.starts_with(RAW_BLOCK_TAG_PREFIX) {
-                            // we need to use the raw HTML value and NOT the escaped one
-                            // elements starting with RAW_BLOCK_TAG_PREFIX are special and shouldn't *actually*
-                            // be rendered the page... this is an alternative to lines starting
-                            // with "@"
-                            token.raw.replace('"', "\\\"")
-                        } else {
-                            token.html.replace('"', "\\\"")
-                        },
-                        token.line
-                    ));
+                    blocks.push((slot, children, *location));
+                }
+                // shared by all fill blocks
+                Node::RustStatement { .. } => self.node(out, child, true, slots)?,
+                Node::Text { text, .. } if text == "\n" => {}
+                _ => {
+                    let label =
+                        format!("only `%f:` blocks and Rust lines can follow `%s:{template}`");
+                    return Err(location_of(child)
+                        .error("unexpected content outside of a fill block", label));
+                }
+            }
+        }
+
+        // the fills see everything the shared Rust lines define, wherever they are
+        for (slot, children, location) in blocks {
+            let fill = self.fill(out, children, location, slots)?;
+            fills.insert(slot.to_owned(), fill);
+        }
+
+        // render the base template around the fills
+        base.body(out, &fills, true)
+    }
+
+    /// Push the closure which renders the `nodes` filling a slot (at `location`),
+    /// returning its name.
+    fn fill(
+        &self,
+        out: &mut Output,
+        nodes: &[Node],
+        location: Location,
+        slots: &Slots,
+    ) -> Result<syn::Ident> {
+        let name = format!("crml_fill_{}", out.fills);
+        out.fills += 1;
+
+        if let (_, true) = out.function(Function::Fill) {
+            // gives the closures the type of the output they render to
+            out.items.push(Code::from(quote! {
+                fn crml_fill<W: ?Sized, F: FnMut(&mut W) -> ::core::fmt::Result>(
+                    _: &mut W,
+                    fill: F,
+                ) -> F {
+                    fill
+                }
+            }));
+        }
+
+        self.code(
+            out,
+            &format!("let mut {name} = crml_fill(crml_rendered, |crml_rendered| {{"),
+            location,
+        );
+
+        // fills are rendered in a closure, where the output can't be flushed
+        let streaming = std::mem::replace(&mut out.streaming, false);
+
+        for node in nodes {
+            self.node(out, node, true, slots)?;
+        }
+
+        out.streaming = streaming;
+        self.code(out, "Ok(())", location);
+        self.code(out, "});", location);
+        Ok(syn::Ident::new(&name, Span::call_site()))
+    }
+
+    /// Load the `template` used at `location`.
+    ///
+    /// `relation` describes how the template is used (`"extends"` or `"includes"`), and is
+    /// shown when a template ends up using itself.
+    fn load(&self, template: &str, location: Location, relation: &str) -> Result<Generator> {
+        let mut chain = self.chain.clone();
+
+        if self.path.is_some() {
+            chain.push(self.name.clone());
+        }
+
+        if let Some(start) = chain.iter().position(|name| name == template) {
+            let cycle: Vec<String> = chain[start..]
+                .iter()
+                .chain([&template.to_string()])
+                .map(|name| format!("`{name}`"))
+                .collect();
+
+            return Err(location.error(
+                format!("template `{template}` {relation} itself"),
+                format!("templates can't use themselves ({})", cycle.join(" -> ")),
+            ));
+        }
+
+        let mut base = self.open(template).map_err(|e| match e.file {
+            // the file could not be read, point at the fill
+            None => self.error(e, location),
+            Some(_) => e,
+        })?;
+
+        base.chain = chain;
+        Ok(base)
+    }
+
+    /// The names of all slots defined in this template.
+    ///
+    /// If `inherited` is true, slots of base templates which this template doesn't fill
+    /// are included, since they can be filled by templates using this one as a base.
+    fn slot_names(&self, inherited: bool) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        self.collect_slot_names(&self.tree, inherited, &mut names)?;
+        Ok(names)
+    }
+
+    /// Collect the names of all slots defined in `nodes`.
+    fn collect_slot_names(
+        &self,
+        nodes: &[Node],
+        inherited: bool,
+        names: &mut Vec<String>,
+    ) -> Result<()> {
+        for node in nodes {
+            match node {
+                Node::Element(element) => {
+                    self.collect_slot_names(&element.children, inherited, names)?
+                }
+                Node::SlotDef { name, children, .. } => {
+                    names.push(name.to_owned());
+                    self.collect_slot_names(children, inherited, names)?;
+                }
+                Node::Fill { children, .. } => {
+                    self.collect_slot_names(children, inherited, names)?
+                }
+                Node::SlotFill {
+                    template,
+                    slot,
+                    children,
+                    location,
+                } => {
+                    self.collect_slot_names(children, inherited, names)?;
+
+                    if !inherited {
+                        continue;
+                    }
+
+                    let filled: Vec<&str> = match slot {
+                        Some(slot) => vec![slot],
+                        None => children
+                            .iter()
+                            .filter_map(|child| match child {
+                                Node::Fill { slot, .. } => Some(slot.as_str()),
+                                _ => None,
+                            })
+                            .collect(),
+                    };
+
+                    for name in self
+                        .load(template, *location, "extends")?
+                        .slot_names(true)?
+                    {
+                        if !filled.contains(&name.as_str()) {
+                            names.push(name);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Push the Rust which renders the given `element` (and its children) to `out`.
+    fn element(&self, out: &mut Output, element: &Element, slots: &Slots) -> Result<()> {
+        let tag = &element.selector.tag;
+        let interpolate = !WHITESPACE_SENSITIVE.contains(&tag.as_str());
+        let mut html = element.selector.clone().render();
+
+        if let Some(ref inline) = element.inline {
+            // inline element
+            html.push_str(&format!("{inline}</{tag}>"));
+        }
+
+        out.push_html(&html, interpolate, self.span_of(element.location))
+            .map_err(|e| self.error(e, element.location))?;
+
+        if element.inline.is_some() || VOID_ELEMENTS.contains(&tag.as_str()) {
+            return Ok(());
+        }
+
+        for child in &element.children {
+            self.node(out, child, interpolate, slots)?;
+        }
+
+        out.push_str(&format!("</{tag}>"));
+        out.boundary();
+        Ok(())
+    }
+
+    /// Point an `error` found while generating a node at the node's `location`.
+    fn error(&self, error: Error, location: Location) -> Error {
+        error
+            .at(location.line, location.column)
+            .in_file(self.file_name())
+    }
+}
+
+/// Get the [`Location`] of any `node`.
+fn location_of(node: &Node) -> Location {
+    match node {
+        Node::Element(element) => element.location,
+        Node::Text { location, .. }
+        | Node::RustStatement { location, .. }
+        | Node::RustExpr { location, .. }
+        | Node::RawHtml { location, .. }
+        | Node::SlotDef { location, .. }
+        | Node::SlotFill { location, .. }
+        | Node::Fill { location, .. }
+        | Node::Requires { location, .. }
+        | Node::Include { location, .. }
+        | Node::Component { location, .. }
+        | Node::Children { location }
+        | Node::Call { location, .. } => *location,
+    }
+}
+
+/// The name of the placeholder macro standing in for generated tokens in [`Output`].
+static CHUNK_PLACEHOLDER: &str = "crml_chunk_";
+/// The name of the marker macro placed before every Rust line in [`Output`].
+static LINE_PLACEHOLDER: &str = "crml_line_";
+/// The prefix of the identifiers standing in for generated code in debug source.
+static DEBUG_PLACEHOLDER: &str = "crml_debug_";
+
+/// A function generated once for an [`Output`], however often it is used.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Function {
+    /// The function rendering the included template file.
+    Include(String),
+    /// The function rendering a component, by its template file and its name.
+    Component(String, String),
+    /// The helper giving slot fill closures the type of their output.
+    Fill,
+}
+
+/// A Rust line pushed to an [`Output`].
+struct RustLine {
+    code: String,
+    location: Location,
+    /// The template file the line is from. Base templates share their output with the
+    /// templates filling their slots.
+    file: String,
+    /// The span the line's tokens are located at.
+    span: Span,
+}
+
+/// The Rust generated for a template.
+///
+/// Rust lines (`- ...`) are kept as source text, since a single line doesn't have to be
+/// a complete token tree on its own (`- if a != b {`). Everything generated by CRML is kept
+/// as tokens, referenced from the source by placeholder macro calls (`crml_chunk_!(0)`)
+/// which are replaced once the full source has been lexed. This way, text is always
+/// emitted as real string literals and never goes through the Rust lexer.
+///
+/// Every Rust line is preceded by a marker (`crml_line_!(0)`), so its tokens can be
+/// located at the line they were written on once lexed.
+///
+/// Static text is buffered until something else is pushed, so adjacent text is
+/// written with a single `write_str`.
+struct Output {
+    /// The span Rust lines are located at until the first line marker.
+    span: Span,
+    source: String,
+    chunks: Vec<TokenStream2>,
+    /// Static text which hasn't been written yet.
+    literal: String,
+    /// The number of bytes of static text pushed so far.
+    size_hint: usize,
+    /// If the output is flushed at element boundaries.
+    streaming: bool,
+    /// The options the output is generated with.
+    options: Options,
+    /// The path of the `crml` crate.
+    krate: syn::Path,
+    /// The fields required by the templates rendered into this output.
+    requires: Vec<Requirement>,
+    /// Items (like component functions) placed before everything else in the output.
+    items: Vec<Code>,
+    /// The names of the functions generated for this output.
+    functions: HashMap<Function, String>,
+    /// The number of slot fill closures defined in this output.
+    fills: usize,
+    /// If the last thing pushed to `source` was a chunk placeholder.
+    in_chunk: bool,
+    /// The Rust lines pushed so far, used to locate delimiter errors.
+    lines: Vec<RustLine>,
+}
+
+impl Output {
+    /// Create a new [`Output`]. Rust written in the template is located at `span`.
+    fn new(span: Span, streaming: bool, options: Options, krate: &syn::Path) -> Self {
+        Self {
+            span,
+            source: String::new(),
+            chunks: Vec::new(),
+            literal: String::new(),
+            size_hint: 0,
+            streaming,
+            options,
+            krate: krate.clone(),
+            requires: Vec::new(),
+            items: Vec::new(),
+            functions: HashMap::new(),
+            fills: 0,
+            in_chunk: false,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Get the name of the given generated `function`, and if it still has to be generated.
+    ///
+    /// Names are numbered, since different templates and components can have the same
+    /// name once they are turned into identifiers (`a/b.crml` and `a_b.crml`).
+    fn function(&mut self, function: Function) -> (String, bool) {
+        if let Some(name) = self.functions.get(&function) {
+            return (name.clone(), false);
+        }
+
+        let index = self.functions.len();
+        let name = match function {
+            Function::Include(ref file) => format!("crml_include_{}_{index}", identifier(file)),
+            Function::Component(ref file, ref component) => format!(
+                "crml_component_{}_{}_{index}",
+                identifier(file),
+                identifier(component)
+            ),
+            Function::Fill => "crml_fill".to_string(),
+        };
+
+        self.functions.insert(function, name.clone());
+        (name, true)
+    }
+
+    /// Push static text.
+    fn push_str(&mut self, value: &str) {
+        self.literal.push_str(value);
+        self.size_hint += value.len();
+    }
+
+    /// Write all buffered static text.
+    fn flush(&mut self) {
+        if !self.literal.is_empty() {
+            let literal = std::mem::take(&mut self.literal);
+            self.chunk(write_str(literal));
+        }
+    }
+
+    /// Mark the end of an element. Streaming output is flushed here.
+    fn boundary(&mut self) {
+        if self.streaming {
+            self.push(quote! { crml_rendered.flush().await?; });
+        }
+    }
+
+    /// Push generated `tokens`.
+    fn push(&mut self, tokens: TokenStream2) {
+        self.flush();
+        self.chunk(tokens);
+    }
+
+    /// Push generated `tokens` into the current chunk, or a new one.
+    fn chunk(&mut self, tokens: TokenStream2) {
+        if self.in_chunk {
+            // extend the previous chunk instead of creating a new one
+            self.chunks
+                .last_mut()
+                .expect("in_chunk implies a chunk exists")
+                .extend(tokens);
+            return;
+        }
+
+        self.source
+            .push_str(&format!("{CHUNK_PLACEHOLDER}!({})\n", self.chunks.len()));
+        self.chunks.push(tokens);
+        self.in_chunk = true;
+    }
+
+    /// Push a Rust line written in the template `file`, located at `span`.
+    fn push_code(&mut self, code: &str, location: Location, file: String, span: Span) {
+        self.flush();
+        self.source.push_str(&format!(
+            "{LINE_PLACEHOLDER}!({}) {code}\n",
+            self.lines.len()
+        ));
+        self.lines.push(RustLine {
+            code: code.to_string(),
+            location,
+            file,
+            span,
+        });
+        self.in_chunk = false;
+    }
+
+    /// If interpolated values should be escaped.
+    ///
+    /// Trusted values are wrapped in `Raw`, which is never escaped.
+    fn escapes(&self) -> bool {
+        self.options.escape == Escape::Html
+    }
+
+    /// Push the Rust which renders the given `html`.
+    ///
+    /// If `interpolate` is true, `{expr}` interpolations in `html` are rendered (and escaped,
+    /// unless escaping is turned off) through `crml::escape`, and located at `span`.
+    /// Otherwise, `html` is pushed literally.
+    fn push_html(&mut self, html: &str, interpolate: bool, span: Span) -> Result<()> {
+        let segments = if interpolate {
+            segments(html)?
+        } else {
+            vec![Segment::Literal(html.to_string())]
+        };
+
+        for segment in segments {
+            match segment {
+                Segment::Literal(literal) => self.push_str(&literal),
+                Segment::Expr {
+                    expr,
+                    context,
+                    escape: true,
+                } if self.escapes() => {
+                    let expr = parse_expr(&expr, span)?;
+                    let krate = &self.krate;
+                    let context = match context {
+                        Context::Text => quote! { #krate::escape::Context::Text },
+                        Context::Attribute => quote! { #krate::escape::Context::Attribute },
+                    };
+
+                    self.push(quote! {
+                        #krate::escape::write_escaped(crml_rendered, &(#expr), #context)?;
+                    });
+                }
+                Segment::Expr { expr, .. } => {
+                    let expr = parse_expr(&expr, span)?;
+                    let krate = &self.krate;
+                    self.push(quote! {
+                        #krate::escape::write_raw(crml_rendered, &(#expr))?;
+                    });
                 }
             }
         }
 
-        format!(
-            "{out}\nfor stack_item_ in crml_templ_stack {{
-    crml_rendered.push_str(&stack_item_);
-}}\ncrml_rendered\n"
-        )
+        Ok(())
+    }
+
+    /// Lex the full source and replace all placeholders with their chunks.
+    fn finish(mut self) -> Result<Code> {
+        self.flush();
+
+        let stream: TokenStream2 = match self.source.parse() {
+            Ok(stream) => stream,
+            Err(e) => {
+                let lines = self.lines.iter().map(|line| line.code.as_str());
+
+                return Err(match unbalanced(lines) {
+                    Some((index, label)) => {
+                        let line = &self.lines[index];
+                        line.location
+                            .error("unbalanced delimiters in Rust lines", label)
+                            .in_file(&line.file)
+                    }
+                    None => Error::new("failed to lex Rust lines", e.to_string()),
+                });
+            }
+        };
+
+        let debug = self.debug();
+        let spans: Vec<Span> = self.lines.iter().map(|line| line.span).collect();
+        let body = replace_chunks(locate(stream, &spans, &mut self.span), &mut self.chunks);
+        let items = self.items.iter().map(|item| &item.tokens);
+
+        Ok(Code {
+            tokens: quote! {
+                #(#items)*
+                #body
+            },
+            debug,
+        })
+    }
+
+    /// The debug source of the output, with the template file and line of every Rust line.
+    fn debug(&self) -> String {
+        let mut debug: Vec<String> = self.items.iter().map(|item| item.debug.clone()).collect();
+
+        for line in self.source.lines() {
+            if let Some(index) = placeholder(line, CHUNK_PLACEHOLDER) {
+                debug.push(self.chunks[index].to_string());
+            } else if let Some(index) = placeholder(line, LINE_PLACEHOLDER) {
+                let RustLine {
+                    code,
+                    location,
+                    file,
+                    ..
+                } = &self.lines[index];
+
+                debug.push(format!("{code} // {file}:{}", location.line));
+            }
+        }
+
+        debug.join("\n")
+    }
+}
+
+/// The index in a placeholder `line` (`crml_chunk_!(0)`) of the given `kind`.
+fn placeholder(line: &str, kind: &str) -> Option<usize> {
+    line.strip_prefix(kind)?
+        .strip_prefix("!(")?
+        .split_once(')')?
+        .0
+        .parse()
+        .ok()
+}
+
+/// The byte range of the given (1-based) `line` of a string literal's value in the
+/// literal's source `repr`, if it can be found.
+fn line_range(repr: &str, line: usize) -> Option<(usize, usize)> {
+    let raw = repr.starts_with('r');
+    let start = repr.find('"')? + 1;
+    let end = repr.rfind('"')?;
+    let mut lines = vec![start];
+    let mut chars = repr[..end].char_indices().skip_while(|(i, _)| *i < start);
+
+    while let Some((i, char)) = chars.next() {
+        match char {
+            '\n' => lines.push(i + 1),
+            '\\' if !raw => match chars.next() {
+                Some((i, 'n')) => lines.push(i + 1),
+                // the newline of a line continuation isn't part of the value
+                Some((_, '\n')) => {}
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    let first = *lines.get(line.checked_sub(1)?)?;
+    let last = lines.get(line).map_or(end, |next| {
+        // stop before the newline (or `\n`) ending the line
+        match repr[..*next].ends_with("\\n") && !raw {
+            true => next - 2,
+            false => next - 1,
+        }
+    });
+
+    Some((first, last.max(first)))
+}
+
+/// Turn a template or component `name` into a valid part of a Rust identifier.
+fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|char| match char.is_ascii_alphanumeric() {
+            true => char,
+            false => '_',
+        })
+        .collect()
+}
+
+/// Write a static `value` to the output.
+fn write_str(value: impl ToTokens) -> TokenStream2 {
+    quote! { ::core::fmt::Write::write_str(crml_rendered, #value)?; }
+}
+
+/// Parse the arguments of an include (`c = 3, d = "text"`), located at `span`.
+fn parse_arguments(arguments: &str, span: Span) -> syn::Result<Vec<(String, TokenStream2)>> {
+    use syn::parse::Parser;
+
+    syn::punctuated::Punctuated::<syn::Expr, syn::Token![,]>::parse_terminated
+        .parse_str(arguments)?
+        .into_iter()
+        .map(|argument| match argument {
+            syn::Expr::Assign(syn::ExprAssign { left, right, .. }) => match *left {
+                syn::Expr::Path(ref path) if path.path.get_ident().is_some() => {
+                    let name = path.path.get_ident().expect("checked above").to_string();
+                    Ok((name, respan(right.to_token_stream(), span)))
+                }
+                _ => Err(syn::Error::new_spanned(left, "expected an argument name")),
+            },
+            argument => Err(syn::Error::new_spanned(
+                argument,
+                "expected `name = value` arguments",
+            )),
+        })
+        .collect()
+}
+
+/// Parse a single Rust expression from a template, located at `span`.
+fn parse_expr(expr: &str, span: Span) -> Result<TokenStream2> {
+    let tokens: TokenStream2 = expr
+        .parse()
+        .map_err(|e: proc_macro2::LexError| Error::new("invalid Rust expression", e.to_string()))?;
+
+    syn::parse2::<syn::Expr>(tokens.clone())
+        .map_err(|e| Error::new("invalid Rust expression", e.to_string()))?;
+
+    Ok(respan(tokens, span))
+}
+
+/// Check the syntax of a single Rust line from a template.
+///
+/// Lines which open or close a block (`- if a != b {`, `- }`) are only complete
+/// together with other lines, so they can't be checked on their own.
+fn check_statement(code: &str) -> Result<()> {
+    if code.ends_with('{') || code.starts_with('}') || unbalanced([code]).is_some() {
+        return Ok(());
+    }
+
+    let statement = if code.ends_with(';') {
+        code.to_string()
+    } else {
+        format!("{code};")
+    };
+
+    syn::parse_str::<syn::Stmt>(&statement)
+        .map(|_| ())
+        .map_err(|e| Error::new("invalid Rust statement", e.to_string()))
+}
+
+/// Locate every token in `stream` at `span`.
+///
+/// The tokens keep resolving at the macro call site, so hygiene is unchanged.
+fn respan(stream: TokenStream2, span: Span) -> TokenStream2 {
+    let span = Span::call_site().located_at(span);
+
+    stream
+        .into_iter()
+        .map(|token| match token {
+            TokenTree::Group(group) => {
+                let mut respanned = Group::new(group.delimiter(), respan(group.stream(), span));
+                respanned.set_span(span);
+                TokenTree::Group(respanned)
+            }
+            mut token => {
+                token.set_span(span);
+                token
+            }
+        })
+        .collect()
+}
+
+/// Locate the tokens of every Rust line in `stream` at the span of the line (in `spans`),
+/// removing the line markers. Tokens before the first marker are located at `span`.
+///
+/// The tokens keep resolving at the macro call site, so hygiene is unchanged.
+fn locate(stream: TokenStream2, spans: &[Span], span: &mut Span) -> TokenStream2 {
+    let mut out = TokenStream2::new();
+    let mut tokens = stream.into_iter();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ref ident) if ident == LINE_PLACEHOLDER => {
+                // crml_line_ ! ( N )
+                tokens.next();
+                let Some(TokenTree::Group(group)) = tokens.next() else {
+                    unreachable!("line markers are always followed by `!(N)`");
+                };
+
+                let index: usize = group
+                    .stream()
+                    .to_string()
+                    .parse()
+                    .expect("line markers always hold an index");
+
+                *span = spans[index];
+            }
+            TokenTree::Group(group) => {
+                // delimiters are located where they are opened
+                let opened = Span::call_site().located_at(*span);
+                let mut located =
+                    Group::new(group.delimiter(), locate(group.stream(), spans, span));
+                located.set_span(opened);
+                out.extend([TokenTree::Group(located)]);
+            }
+            mut token => {
+                token.set_span(Span::call_site().located_at(*span));
+                out.extend([token]);
+            }
+        }
+    }
+
+    out
+}
+
+/// Replace all chunk placeholders in `stream` with their chunk.
+fn replace_chunks(stream: TokenStream2, chunks: &mut [TokenStream2]) -> TokenStream2 {
+    let mut out = TokenStream2::new();
+    let mut tokens = stream.into_iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ref ident) if ident == CHUNK_PLACEHOLDER => {
+                // crml_chunk_ ! ( N )
+                tokens.next();
+                let Some(TokenTree::Group(group)) = tokens.next() else {
+                    unreachable!("placeholders are always followed by `!(N)`");
+                };
+
+                let index: usize = group
+                    .stream()
+                    .to_string()
+                    .parse()
+                    .expect("placeholders always hold an index");
+
+                out.extend(std::mem::take(&mut chunks[index]));
+            }
+            TokenTree::Group(group) => {
+                let mut replaced =
+                    Group::new(group.delimiter(), replace_chunks(group.stream(), chunks));
+
+                replaced.set_span(group.span());
+                out.extend([TokenTree::Group(replaced)]);
+            }
+            token => out.extend([token]),
+        }
+    }
+
+    out
+}
+
+/// Find the index of the Rust line which leaves its delimiters unbalanced, if any.
+fn unbalanced<'a>(lines: impl IntoIterator<Item = &'a str>) -> Option<(usize, &'static str)> {
+    let mut open: Vec<(char, usize)> = Vec::new();
+
+    for (index, code) in lines.into_iter().enumerate() {
+        for char in code.chars() {
+            match char {
+                '{' | '(' | '[' => open.push((char, index)),
+                '}' | ')' | ']' => {
+                    let expected = match char {
+                        '}' => '{',
+                        ')' => '(',
+                        _ => '[',
+                    };
+
+                    match open.pop() {
+                        Some((opened, _)) if opened == expected => {}
+                        _ => {
+                            return Some((
+                                index,
+                                "this line closes a delimiter which was never opened",
+                            ));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    open.last()
+        .map(|(_, index)| (*index, "this line opens a delimiter which is never closed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::tests::Tree;
+
+    /// A tree of templates, with a configuration loading them from its root.
+    fn templates(name: &str, files: &[(&str, &str)]) -> (Tree, Config) {
+        let mut files = files.to_vec();
+        files.push(("crml.json", r#"{ "root_dir": "." }"#));
+
+        let tree = Tree::new(name, &files);
+        let config = tree.discover("").unwrap();
+        (tree, config)
+    }
+
+    fn generate(config: &Config, name: &str) -> Result<Generated> {
+        Generator::from_file(name, config, Span::call_site())?.generate(false)
+    }
+
+    fn error(config: &Config, name: &str) -> Error {
+        match generate(config, name) {
+            Ok(_) => panic!("expected `{name}` to fail"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn load_detects_cycles() {
+        let (_tree, config) = templates(
+            "cycles",
+            &[
+                ("a.crml", "%s:b\n%f:body'a"),
+                ("b.crml", "%s:a\n%f:body'b"),
+                ("c.crml", "%p\n    %include:c"),
+            ],
+        );
+
+        let extends = error(&config, "a");
+        assert_eq!(extends.message, "template `a` extends itself");
+        assert_eq!(
+            extends.label,
+            "templates can't use themselves (`a` -> `b` -> `a`)"
+        );
+        assert_eq!(extends.file.as_deref(), Some("b.crml"));
+        assert_eq!(extends.line, 1);
+
+        let includes = error(&config, "c");
+        assert_eq!(includes.message, "template `c` includes itself");
+        assert_eq!(includes.line, 2);
+    }
+
+    #[test]
+    fn inherited_slot_names() {
+        let (_tree, config) = templates(
+            "slots",
+            &[
+                (
+                    "base.crml",
+                    "%html\n    %slot[name=\"head\"]\n    %slot[name=\"body\"]",
+                ),
+                (
+                    "layout.crml",
+                    "%s:base\n%f:body\n    %main\n        %slot[name=\"content\"]",
+                ),
+                (
+                    "sidebar.crml",
+                    "%s:layout.content\n%aside\n    %slot[name=\"side\"]",
+                ),
+                ("page.crml", "%s:sidebar\n%f:head'title\n%f:side'side"),
+            ],
+        );
+
+        let open = |name: &str| Generator::from_file(name, &config, Span::call_site()).unwrap();
+
+        assert_eq!(open("layout").slot_names(false).unwrap(), ["content"]);
+        assert_eq!(
+            open("layout").slot_names(true).unwrap(),
+            ["content", "head"]
+        );
+        assert_eq!(open("sidebar").slot_names(true).unwrap(), ["side", "head"]);
+        assert!(generate(&config, "page").is_ok());
+    }
+
+    #[test]
+    fn line_ranges() {
+        // `"a\nbc"`
+        assert_eq!(line_range(r#""a\nbc""#, 1), Some((1, 2)));
+        assert_eq!(line_range(r#""a\nbc""#, 2), Some((4, 6)));
+        assert_eq!(line_range(r#""a\nbc""#, 3), None);
+        assert_eq!(line_range(r#""a\nbc""#, 0), None);
+
+        // a raw string with a real newline
+        assert_eq!(line_range("r#\"a\nbc\"#", 1), Some((3, 4)));
+        assert_eq!(line_range("r#\"a\nbc\"#", 2), Some((5, 7)));
+
+        // line continuations don't start a new line
+        assert_eq!(line_range("\"a\\\n  b\"", 1), Some((1, 7)));
+        assert_eq!(line_range("\"a\\\n  b\"", 2), None);
+    }
+
+    #[test]
+    fn unbalanced_lines() {
+        assert_eq!(unbalanced(["if a {", "foo(b[0])", "}"]), None);
+        assert_eq!(
+            unbalanced(["if a {", "foo("]),
+            Some((1, "this line opens a delimiter which is never closed"))
+        );
+        assert_eq!(
+            unbalanced(["a", "}"]),
+            Some((1, "this line closes a delimiter which was never opened"))
+        );
+        assert_eq!(
+            unbalanced(["foo(", "]"]),
+            Some((1, "this line closes a delimiter which was never opened"))
+        );
     }
 }
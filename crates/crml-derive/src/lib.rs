@@ -1,50 +1,39 @@
-use serde::{Serialize, Deserialize};
-use pathbufd::PathBufD;
-
-use std::sync::LazyLock;
-use std::fs::{read_to_string, File};
-
+mod args;
+mod config;
 mod generator;
 
-/// The `./crml.json` file.
-#[derive(Serialize, Deserialize, Clone)]
-struct Config {
-    /// The root directory to load all templates from.
-    ///
-    /// # Example
-    /// ```json
-    /// {
-    ///     "root_dir": "./templates"
-    /// }
-    /// ```
-    pub root_dir: PathBufD,
-}
-
-// read config to constant
-static CONFIG: LazyLock<Config> = LazyLock::new(|| {
-    serde_json::from_str::<Config>(
-        &read_to_string(PathBufD::current().join("crml.json"))
-            .expect("failed to read configuration"),
-    )
-    .expect("failed to deserialize configuration")
-});
+use args::TemplateArgs;
+use config::Config;
+use generator::{Code, Generated};
 
 // macro
-use syn::{parse_macro_input, LitStr, ItemStruct};
-use quote::{quote, ToTokens};
+use syn::{parse_macro_input, DeriveInput, Data, Fields};
+use quote::{quote, format_ident, ToTokens};
 use proc_macro::TokenStream;
-use proc_macro2::TokenStream as TokenStream2;
-
-pub(crate) fn get_file(file: &str) -> File {
-    File::open(PathBufD::current().extend(&[CONFIG.root_dir.to_string(), format!("{}.crml", file)]))
-        .expect("failed to read included file")
-}
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use proc_macro_crate::{crate_name, FoundCrate};
 
 // yes this is an attribute macro and not a derive macro, it used to be derive
-/// Mark a struct as a template and provide the name of the template file it uses.
+/// Mark a struct or enum as a template and provide the name of the template file it uses.
+///
+/// Every field is available in the template by reference under its own name, with the
+/// fields of tuple structs named `_0`, `_1` and so on.
+///
+/// Small templates can be given as source instead, with `#[template(source = "...")]`.
+///
+/// The configured defaults can be overridden for a single template with these options:
+///
+/// - `path = "pages/index.crml"`: the path of the template file (with its extension),
+///   instead of its name
+/// - `ext = "html"`: the extension of the template file
+/// - `escape = "html"` or `escape = "none"`: how interpolated values are escaped
+/// - `whitespace = "preserve"` or `whitespace = "trim"`: if blank lines and the whitespace
+///   around text are rendered
+/// - `crate = "path::to::crml"`: the path of the `crml` crate, if it can't be found from
+///   `Cargo.toml` (like when it is re-exported by another crate)
 ///
 /// # Example
-/// ```rust
+/// ```rust,ignore
 /// use crml::{template, Template}; // import template macro *and* Template trait
 ///
 /// #[template("mycrmlfile")]
@@ -57,62 +46,555 @@ pub(crate) fn get_file(file: &str) -> File {
 ///     println!("rendered: {}", MyStruct { a: 1 }.render());
 /// }
 /// ```
+///
+/// ```rust,ignore
+/// #[template(source = "%p'a is {a}")]
+/// struct Inline {
+///     a: i32
+/// }
+/// ```
+///
+/// The variants of an enum can have their own templates. Variants without one are
+/// rendered with the enum's template (where `self` can be matched on):
+///
+/// ```rust,ignore
+/// #[template("alert")]
+/// enum Alert {
+///     #[template(source = "%p.info'{_0}")]
+///     Info(String),
+///     Error { message: String },
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn template(args: TokenStream, input: TokenStream) -> TokenStream {
     // parse args
-    let args = parse_macro_input!(args as LitStr);
-    let file_name = args.value();
+    let args = parse_macro_input!(args as TemplateArgs);
 
     // parse tokens
-    let input = parse_macro_input!(input as ItemStruct);
-
-    let struct_ident = input.ident.clone();
-    let mut struct_tokens = TokenStream2::new();
-    input.to_tokens(&mut struct_tokens);
-
-    // read file into generator
-    let generated = generator::Generator::from_file(get_file(&file_name)).consume();
-
-    let generated_tokens: TokenStream2 = match generated.parse() {
-        Ok(t) => t,
-        Err(e) => {
-            // debug outputs
-            if std::fs::exists("crml_dbg").expect("failed to check for debug dir") == true {
-                println!(
-                    "Debug directory found. Check \"crml_dbg/{}.rs\" to debug template.",
-                    file_name
-                );
-
-                std::fs::write(
-                    format!("crml_dbg/{file_name}.rs"),
-                    format!("fn debug() {{\n{generated}\n}}"),
-                )
-                .expect("failed to write debug file")
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let expanded = expand(args, &input, "template");
+
+    // keep the item (even if it has errors, so using it doesn't cause more errors)
+    strip(&mut input);
+    let mut tokens = input.to_token_stream();
+
+    match expanded {
+        Ok(expanded) => tokens.extend(expanded),
+        Err(e) => tokens.extend(e.to_compile_error()),
+    }
+
+    tokens.into()
+}
+
+/// Implement `Template` for a struct or enum, with the template given in a `#[crml(...)]`
+/// attribute.
+///
+/// This is the same as the `template` attribute macro, which lets it be used next to other
+/// derives. Templates of enum variants are given in `#[crml(...)]` attributes too.
+///
+/// Fields holding trusted HTML can be marked with `#[crml(escape = false)]` (in both the
+/// derive and the attribute macro). They are bound as `crml::escape::Raw(&html)`, which is
+/// never escaped when it is interpolated or pushed (`{html}` or `= html`).
+///
+/// # Example
+/// ```rust,ignore
+/// use crml::Template;
+///
+/// #[derive(Template, Debug)]
+/// #[crml(path = "pages/article.crml")]
+/// struct Article {
+///     title: String,
+///     #[crml(escape = false)]
+///     body: String,
+/// }
+/// ```
+#[proc_macro_derive(Template, attributes(crml))]
+pub fn derive_template(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    helper_args(&input.attrs, "crml")
+        .and_then(|args| match args {
+            Some((args, _)) => expand(args, &input, "crml"),
+            // enums can leave it out if every variant has a template
+            None if matches!(input.data, Data::Enum(_)) => {
+                expand(TemplateArgs::default(), &input, "crml")
             }
+            None => Err(syn::Error::new_spanned(
+                &input.ident,
+                "expected a `#[crml(...)]` attribute giving the template",
+            )),
+        })
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Generate the `Template` implementation of the item, given the `args` of its template.
+///
+/// Enum variants have their templates given in `helper` attributes.
+fn expand(args: TemplateArgs, input: &DeriveInput, helper: &str) -> syn::Result<TokenStream2> {
+    let ident = input.ident.clone();
 
-            // panic :(
-            panic!("{}", e.to_string())
+    // read config, templates given as source only need it to use other templates
+    let config = Config::discover();
+
+    // rebuild when the configuration changes
+    let config_path = config.as_ref().ok().map(|config| {
+        let path = config.source.to_string();
+        quote! { const _: &[u8] = include_bytes!(#path); }
+    });
+
+    // the options given in the attribute override the configured ones
+    let options = args.options(config.as_ref().map(|c| c.options).unwrap_or_default());
+    let krate = crate_path(&args);
+
+    // the templates rendering the item, and the fields they're rendered with
+    let (render, streamed, size_hint) = match input.data {
+        Data::Struct(ref data) => {
+            let template = generate(
+                &args,
+                &config,
+                options,
+                &krate,
+                &ident.to_string(),
+                &data.fields,
+            )?
+            .ok_or_else(|| TemplateArgs::missing(Span::call_site()))?;
+            let fields = match bind(&data.fields) {
+                Some(pattern) => quote! {
+                    #[allow(unused_variables)]
+                    let Self #pattern = self;
+                },
+                None => TokenStream2::new(),
+            };
+
+            // the fields are bound before the template renders
+            let body = |generated: &Generated| {
+                Code::assemble(&[&generated.code], |tokens| {
+                    let tokens = &tokens[0];
+                    quote! { #fields #tokens }
+                })
+            };
+
+            (
+                body(&template.render),
+                template.streamed.as_ref().map(body),
+                template.render.size_hint,
+            )
+        }
+        Data::Enum(ref data) => {
+            let fallback = generate(
+                &args,
+                &config,
+                options,
+                &krate,
+                &ident.to_string(),
+                &Fields::Unit,
+            )?;
+            let mut arms = Vec::new();
+            let mut size_hint = fallback.as_ref().map_or(0, |t| t.render.size_hint);
+            let mut complete = true;
+
+            for variant in &data.variants {
+                // the variant's own template
+                let Some((variant_args, attr)) = helper_args(&variant.attrs, helper)? else {
+                    complete = false;
+                    continue;
+                };
+
+                if let Some(ref path) = variant_args.krate {
+                    return Err(syn::Error::new_spanned(
+                        path,
+                        format!("`crate` can only be given for the whole of `{ident}`"),
+                    ));
+                }
+
+                let name = format!("{ident}::{}", variant.ident);
+                let options = variant_args.options(options);
+                let template = generate(
+                    &variant_args,
+                    &config,
+                    options,
+                    &krate,
+                    &name,
+                    &variant.fields,
+                )?
+                .ok_or_else(|| TemplateArgs::missing(syn::spanned::Spanned::span(attr)))?;
+
+                size_hint = size_hint.max(template.render.size_hint);
+                arms.push((variant.ident.clone(), bind(&variant.fields), template));
+            }
+
+            if !complete && fallback.is_none() {
+                let missing = data
+                    .variants
+                    .iter()
+                    .find(|variant| !arms.iter().any(|(ident, ..)| *ident == variant.ident))
+                    .expect("a variant is missing a template");
+
+                return Err(syn::Error::new_spanned(
+                    &missing.ident,
+                    format!(
+                        "variant `{}` has no template, expected a `#[{helper}(...)]` attribute on it or on `{ident}`",
+                        missing.ident
+                    ),
+                ));
+            }
+
+            // variants without a template of their own render the enum's template
+            let fallback = match complete {
+                true => None,
+                false => fallback,
+            };
+
+            let render = variants(&arms, fallback.as_ref(), |template| Some(&template.render));
+            let streamed = match cfg!(feature = "async") {
+                true => Some(variants(&arms, fallback.as_ref(), |template| {
+                    template.streamed.as_ref()
+                })),
+                false => None,
+            };
+
+            (render, streamed, size_hint)
+        }
+        Data::Union(ref data) => {
+            return Err(syn::Error::new_spanned(
+                data.union_token,
+                "templates can only be used on structs and enums",
+            ));
         }
     };
 
     // build output
-    let expanded = quote! {
-        #struct_tokens
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let codes: Vec<&Code> = std::iter::once(&render).chain(streamed.as_ref()).collect();
+    let expanded = Code::assemble(&codes, |bodies| {
+        let render = &bodies[0];
+
+        // streaming render, for the async feature
+        let streamed = bodies.get(1).map(|streamed_tokens| {
+            quote! {
+                fn render_into_async<W: #krate::stream::AsyncWrite + Unpin + ?Sized>(
+                    &self,
+                    out: &mut W,
+                ) -> impl ::core::future::Future<Output = ::std::io::Result<()>> {
+                    async move {
+                        let mut crml_stream = #krate::stream::Stream::new(out, Self::SIZE_HINT);
+                        let result: ::core::fmt::Result = async {
+                            let crml_rendered = &mut crml_stream;
+                            #streamed_tokens
+                        }
+                        .await;
 
-        impl crml::Template for #struct_ident {
-            fn render(self) -> String {
-                #generated_tokens
+                        crml_stream.finish(result).await
+                    }
+                }
+            }
+        });
+
+        quote! {
+            #config_path
+
+            impl #impl_generics #krate::Template for #ident #ty_generics #where_clause {
+                const SIZE_HINT: usize = #size_hint;
+
+                fn render_into<W: ::core::fmt::Write + ?Sized>(
+                    &self,
+                    crml_rendered: &mut W,
+                ) -> ::core::fmt::Result {
+                    #render
+                }
+
+                #streamed
             }
         }
-    };
+    });
 
     // debug outputs
-    if std::fs::exists("crml_dbg").expect("failed to check for debug dir") == true {
-        let file_name = file_name.replace("/", "_");
-        std::fs::write(format!("crml_dbg/{file_name}.rs"), expanded.to_string())
+    if std::fs::exists("crml_dbg").expect("failed to check for debug dir") {
+        let file_name = match args.template() {
+            Some(template) if args.source.is_none() => template.value().replace("/", "_"),
+            _ => ident.to_string(),
+        };
+
+        std::fs::write(format!("crml_dbg/{file_name}.rs"), expanded.debug)
             .expect("failed to write debug file")
     }
 
     // return
-    expanded.into()
+    Ok(expanded.tokens)
+}
+
+/// A template generated for a struct, an enum or one of its variants.
+struct Template {
+    /// The body of `render_into`.
+    render: Generated,
+    /// The body of `render_into_async`, for the async feature.
+    streamed: Option<Generated>,
+}
+
+/// Generate the template given with `args` (if any) for data with the given `fields`.
+///
+/// Templates given as source are named `name`.
+fn generate(
+    args: &TemplateArgs,
+    config: &crml_core::Result<Config>,
+    options: config::Options,
+    krate: &syn::Path,
+    name: &str,
+    fields: &Fields,
+) -> syn::Result<Option<Template>> {
+    let Some(literal) = args.template() else {
+        return Ok(None);
+    };
+
+    let span = literal.span();
+    let raw = raw_fields(fields)?;
+    let config = match (config, &args.source) {
+        (Ok(config), _) => Some(config),
+        (Err(_), Some(_)) => None,
+        (Err(e), None) => return Err(syn::Error::new(span, e)),
+    };
+
+    // the template's name, and the file its errors are shown in
+    let (file_name, file) = match (&args.name, &args.path, config) {
+        (Some(name), _, Some(config)) => {
+            let ext = match args.ext {
+                Some(ref ext) => ext.value().trim_start_matches('.').to_string(),
+                None => config.ext.clone(),
+            };
+
+            (name.value(), format!("{}.{ext}", name.value()))
+        }
+        (_, Some(path), Some(config)) => {
+            let path = path.value();
+            let ext = match args.ext {
+                Some(ref ext) => ext.value().trim_start_matches('.').to_string(),
+                None => config.ext.clone(),
+            };
+
+            let name = path.strip_suffix(&format!(".{ext}")).unwrap_or(&path);
+            (name.to_string(), path)
+        }
+        _ => (name.to_string(), format!("<{name} source>")),
+    };
+
+    // read template into generator
+    let generator = match (&args.source, config) {
+        (Some(source), config) => {
+            generator::Generator::from_source(&file_name, file.clone(), source.value(), span).map(
+                |generator| {
+                    generator
+                        .with_config(config.cloned())
+                        .with_literal(source.token())
+                },
+            )
+        }
+        (None, Some(config)) => {
+            generator::Generator::from_path(&file_name, file.clone(), config, span)
+        }
+        (None, None) => unreachable!("checked above"),
+    };
+
+    let (render, streamed) = generator
+        .map(|g| {
+            g.with_options(options)
+                .with_raw(raw)
+                .with_crate(krate.clone())
+        })
+        .and_then(|generator| {
+            let streamed = match cfg!(feature = "async") {
+                true => Some(generator.generate(true)?),
+                false => None,
+            };
+
+            Ok((generator.generate(false)?, streamed))
+        })
+        .map_err(|e| syn::Error::new(span, e.in_file(file.clone())))?;
+
+    // check the fields required by the template
+    let field_names: Vec<String> = names(fields).map(|ident| ident.to_string()).collect();
+
+    for requirement in &render.requires {
+        if !field_names.contains(&requirement.field) {
+            let error = requirement.error.clone().in_file(file);
+            return Err(syn::Error::new(span, error));
+        }
+    }
+
+    Ok(Some(Template { render, streamed }))
+}
+
+/// The names the given `fields` are bound as in templates.
+///
+/// Fields of tuple structs and variants are named `_0`, `_1` and so on.
+fn names(fields: &Fields) -> impl Iterator<Item = syn::Ident> + '_ {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match field.ident {
+            Some(ref ident) => ident.clone(),
+            None => format_ident!("_{i}"),
+        })
+}
+
+/// The path of the `crml` crate in the crate being compiled.
+///
+/// Unless it is given with `crate = "..."`, this is found from the dependencies in
+/// `Cargo.toml`, so renamed dependencies (and depending on `crml-core` directly) work.
+fn crate_path(args: &TemplateArgs) -> syn::Path {
+    if let Some(ref path) = args.krate {
+        return path.clone();
+    }
+
+    for name in ["crml", "crml-core"] {
+        let ident = match crate_name(name) {
+            Ok(FoundCrate::Itself) => {
+                let ident = format_ident!("{}", name.replace('-', "_"));
+
+                // examples, tests and benches of the crate still use it by name
+                if std::env::var("CARGO_CRATE_NAME").is_ok_and(|crate_name| ident == crate_name) {
+                    return syn::parse_quote!(crate);
+                }
+
+                ident
+            }
+            Ok(FoundCrate::Name(name)) => format_ident!("{name}"),
+            Err(_) => continue,
+        };
+
+        return syn::parse_quote!(::#ident);
+    }
+
+    syn::parse_quote!(::crml)
+}
+
+/// Get the template given in the `helper` attribute (`#[crml(...)]`) of an item, if any.
+fn helper_args<'a>(
+    attrs: &'a [syn::Attribute],
+    helper: &str,
+) -> syn::Result<Option<(TemplateArgs, &'a syn::Attribute)>> {
+    let mut args = None;
+
+    for attr in attrs {
+        if !attr.path().is_ident(helper) {
+            continue;
+        }
+
+        if args.is_some() {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "only one template can be given",
+            ));
+        }
+
+        args = Some((attr.parse_args::<TemplateArgs>()?, attr));
+    }
+
+    Ok(args)
+}
+
+/// The names of the `fields` marked with `#[crml(escape = false)]`.
+fn raw_fields(fields: &Fields) -> syn::Result<Vec<String>> {
+    let mut raw = Vec::new();
+
+    for (field, name) in fields.iter().zip(names(fields)) {
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("crml"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if !meta.path.is_ident("escape") {
+                    return Err(meta.error("unknown field option, expected `escape`"));
+                }
+
+                let escape: syn::LitBool = meta.value()?.parse()?;
+
+                if !escape.value {
+                    raw.push(name.to_string());
+                }
+
+                Ok(())
+            })?;
+        }
+    }
+
+    Ok(raw)
+}
+
+/// Remove the helper attributes of the `template` attribute macro from `input`, since
+/// they don't exist outside of it.
+fn strip(input: &mut DeriveInput) {
+    let is_helper = |attr: &syn::Attribute| attr.path().is_ident("crml");
+
+    match input.data {
+        Data::Struct(ref mut data) => {
+            for field in data.fields.iter_mut() {
+                field.attrs.retain(|attr| !is_helper(attr));
+            }
+        }
+        Data::Enum(ref mut data) => {
+            for variant in data.variants.iter_mut() {
+                variant
+                    .attrs
+                    .retain(|attr| !attr.path().is_ident("template"));
+
+                for field in variant.fields.iter_mut() {
+                    field.attrs.retain(|attr| !is_helper(attr));
+                }
+            }
+        }
+        Data::Union(_) => {}
+    }
+}
+
+/// The pattern binding every one of the given `fields` by reference, if there are any.
+fn bind(fields: &Fields) -> Option<TokenStream2> {
+    let idents = names(fields);
+
+    match fields {
+        Fields::Named(_) => Some(quote! { { #(#idents),* } }),
+        Fields::Unnamed(_) => Some(quote! { ( #(#idents),* ) }),
+        Fields::Unit => None,
+    }
+}
+
+/// Match on the variants of an enum, rendering every variant in `arms` with its own
+/// template and the others with the `fallback` template.
+///
+/// `generated` picks the generated body of a template to use.
+fn variants(
+    arms: &[(syn::Ident, Option<TokenStream2>, Template)],
+    fallback: Option<&Template>,
+    generated: impl Fn(&Template) -> Option<&Generated>,
+) -> Code {
+    let empty = Code::default();
+    let codes: Vec<&Code> = arms
+        .iter()
+        .map(|(.., template)| template)
+        .chain(fallback)
+        .map(|template| generated(template).map_or(&empty, |generated| &generated.code))
+        .collect();
+
+    Code::assemble(&codes, |bodies| {
+        let fallback = bodies
+            .get(arms.len())
+            .map(|tokens| quote! { _ => { #tokens } });
+        let arms = arms
+            .iter()
+            .zip(&bodies)
+            .map(|((variant, pattern, _), tokens)| {
+                quote! {
+                    #[allow(unused_variables)]
+                    Self::#variant #pattern => { #tokens }
+                }
+            });
+
+        quote! {
+            match self {
+                #(#arms)*
+                #fallback
+            }
+        }
+    })
 }
@@ -0,0 +1,180 @@
+//! Render throughput of `examples/simple`, compared to the code CRML used to generate.
+//!
+//! Run with `cargo bench -p crml`. The legacy functions must be kept up to date with the
+//! templates; the benchmark fails if they render something else.
+// the legacy functions are written exactly as they used to be generated
+#![allow(clippy::useless_format, clippy::single_char_add_str)]
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use crml::{template, Template};
+
+#[template("index")]
+pub(crate) struct TestProps {
+    a: i32,
+}
+
+#[template("other")]
+pub(crate) struct OtherProps {
+    c: i32,
+}
+
+/// The number of renders per measurement.
+const ITERATIONS: u32 = 100_000;
+
+/// `index.crml`, rendered the way generated code used to: every line is formatted into
+/// a temporary `String`, and nested templates are rendered to their own `String`.
+fn legacy_index(props: &TestProps) -> String {
+    let mut crml_rendered = String::new();
+    let mut crml_templ_stack: Vec<String> = Vec::new();
+
+    // base template
+    let template_ = legacy_first();
+    let template_split_: Vec<&str> = template_.split("<slot name=\"body_content\"/>").collect();
+    let template_half_0_ = template_split_.first().unwrap();
+    let template_half_1_ = template_split_.get(1).unwrap();
+    crml_rendered = format!("{template_half_0_}\n{crml_rendered}\n");
+    crml_templ_stack.push(template_half_1_.to_string());
+
+    crml_rendered.push_str("\n");
+    let a = props.a;
+    let b = 2;
+    crml_rendered.push_str("\n");
+    crml_rendered.push_str(&format!("<div class=\"class \" id=\"id\" attr=\"value\">"));
+    crml_rendered.push_str(&format!("a is {a}, b is {b}"));
+    crml_rendered.push_str(&format!("</div>"));
+    crml_rendered.push_str("\n");
+    crml_rendered.push_str(&format!(
+        " <b>This block contains unsanitized, raw HTML</b> <script> console.log(This is another way to use whitespace-sensitive elements.\"); </script>"
+    ));
+    crml_rendered.push_str("\n");
+
+    if a != b {
+        crml_rendered.push_str(&format!("<h1>a is not equal to b ({a} != {b})</h1>"));
+        crml_rendered.push_str("\n");
+        crml_rendered.push_str(&legacy_other(&OtherProps { c: 3 }));
+        crml_rendered.push_str("\n");
+        crml_rendered.push_str(&format!("<script>"));
+        crml_rendered.push_str(&format!("console.log(\"Hello, world!\");"));
+        crml_rendered.push_str("\n");
+        crml_rendered.push_str(&format!("function test(a, b) {{"));
+        crml_rendered.push_str(&format!("return a + b;"));
+        crml_rendered.push_str(&format!("}}"));
+        crml_rendered.push_str(&format!("</script>"));
+    } else {
+        crml_rendered.push_str(&format!("<h1> a is equal to b ({a} == {b})</h1>"));
+    }
+
+    crml_rendered.push_str("\n");
+    crml_rendered.push_str(&legacy_card("Components", format!("<p>a is {a}</p>")));
+
+    for stack_item_ in crml_templ_stack {
+        crml_rendered.push_str(&stack_item_);
+    }
+
+    crml_rendered
+}
+
+/// `first.crml`, rendered the way generated code used to.
+fn legacy_first() -> String {
+    let mut crml_rendered = String::new();
+    crml_rendered.push_str(&format!("<!DOCTYPE html>"));
+    crml_rendered.push_str(&format!("<html>"));
+    crml_rendered.push_str(&format!("<head>"));
+    crml_rendered.push_str(&format!("<title>"));
+    // the default content of the `title` slot
+    crml_rendered.push_str(&format!("Document Title"));
+    crml_rendered.push_str(&format!("</title>"));
+    crml_rendered.push_str(&format!("</head>"));
+    crml_rendered.push_str("\n");
+    crml_rendered.push_str(&format!("<body>"));
+    crml_rendered.push_str("<slot name=\"body_content\"/>");
+    crml_rendered.push_str(&format!("</body>"));
+    crml_rendered.push_str(&format!("</html>"));
+    crml_rendered.push_str("\n");
+    crml_rendered
+}
+
+/// `other.crml`, rendered the way generated code used to.
+fn legacy_other(props: &OtherProps) -> String {
+    let mut crml_rendered = String::new();
+    let mut c = props.c;
+    crml_rendered.push_str(&format!("<div style=\"background: red; color: white\">"));
+    crml_rendered.push_str(&format!("This is from another template. c = {c}"));
+    crml_rendered.push_str(&format!("</div>"));
+    crml_rendered.push_str("\n");
+    c = 4;
+    crml_rendered.push_str(&format!(
+        "<pre class=\"class \" id=\"id1\" attr=\"value\" secondattr=\"value1\">"
+    ));
+    crml_rendered.push_str(&format!("<code>"));
+    crml_rendered.push_str(&format!("changed mutable c to {c}"));
+    crml_rendered.push_str(&format!("</code>"));
+    crml_rendered.push_str(&format!("</pre>"));
+    crml_rendered
+}
+
+/// The `card` component of `components.crml`, rendered the way generated code used to.
+fn legacy_card(title: &str, children: String) -> String {
+    let mut crml_rendered = String::new();
+    crml_rendered.push_str(&format!("<div class=\"card \">"));
+    crml_rendered.push_str(&format!("<h2>{title}</h2>"));
+    crml_rendered.push_str(&children);
+    crml_rendered.push_str(&format!("</div>"));
+    crml_rendered
+}
+
+/// Collapse the whitespace in rendered `html`, which the legacy functions don't keep.
+fn normalize(html: &str) -> String {
+    html.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .replace("> <", "><")
+}
+
+/// Make sure the legacy functions still render the same page as the templates, so both
+/// sides of the comparison do the same work.
+fn check(props: &TestProps) {
+    assert_eq!(
+        normalize(&props.render()),
+        normalize(&legacy_index(props)),
+        "the legacy functions are out of date with the templates in `examples/simple`"
+    );
+}
+
+/// Measure how long `ITERATIONS` calls of `render` take, returning the rendered bytes.
+fn measure(name: &str, render: impl Fn() -> String) -> Duration {
+    // warm up
+    let mut bytes: usize = 0;
+
+    for _ in 0..(ITERATIONS / 10) {
+        bytes = black_box(render()).len();
+    }
+
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        black_box(render());
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{name:>10}: {:>8.2?} per render, {:>8.2} MB/s",
+        elapsed / ITERATIONS,
+        (bytes as f64 * ITERATIONS as f64) / elapsed.as_secs_f64() / 1_000_000.0
+    );
+
+    elapsed
+}
+
+fn main() {
+    let props = TestProps { a: 1 };
+    check(&props);
+
+    let current = measure("crml", || props.render());
+    let legacy = measure("legacy", || legacy_index(&props));
+
+    println!(
+        "crml renders {:.2}x as fast as the legacy output",
+        legacy.as_secs_f64() / current.as_secs_f64()
+    );
+}
@@ -1,2 +1,5 @@
-pub use crml_derive::template;
+pub use crml_derive::{template, Template};
 pub use crml_core::Template;
+pub use crml_core::escape;
+#[cfg(feature = "async")]
+pub use crml_core::stream;
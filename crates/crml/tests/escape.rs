@@ -0,0 +1,100 @@
+use crml::Template;
+
+#[derive(Template)]
+#[crml(
+    source = "%article\n    {body}\n%ul\n    - for body in comments {\n        %li'{body}\n    - }"
+)]
+struct Post {
+    #[crml(escape = false)]
+    body: String,
+    comments: Vec<String>,
+}
+
+#[derive(Template)]
+#[crml(source = "%p'{body.trim()}\n%p'{body}")]
+struct Trimmed {
+    #[crml(escape = false)]
+    body: String,
+}
+
+#[derive(Template)]
+#[crml(source = "%requires(body: &str)\n%p'{body}")]
+struct Required {
+    #[crml(escape = false)]
+    body: String,
+}
+
+#[derive(Template)]
+#[crml(source = "%a[title={title}]'link")]
+struct Link {
+    title: String,
+}
+
+#[derive(Template)]
+#[crml(source = "%p'{!done} {!= html}")]
+struct Marked {
+    done: bool,
+    html: String,
+}
+
+#[test]
+fn raw_fields_are_not_escaped() {
+    let post = Post {
+        body: "<b>bold</b>".to_string(),
+        comments: Vec::new(),
+    };
+
+    assert!(post.render().contains("<b>bold</b>"));
+
+    let required = Required {
+        body: "<b>bold</b>".to_string(),
+    };
+
+    assert_eq!(required.render(), "<p><b>bold</b></p>");
+}
+
+#[test]
+fn shadowing_locals_are_escaped() {
+    let post = Post {
+        body: String::new(),
+        comments: vec!["<script>alert(1)</script>".to_string()],
+    };
+
+    let rendered = post.render();
+    assert!(rendered.contains("<li>&lt;script&gt;alert(1)&lt;/script&gt;</li>"));
+    assert!(!rendered.contains("<script>"));
+}
+
+#[test]
+fn values_computed_from_raw_fields_are_escaped() {
+    let trimmed = Trimmed {
+        body: " <b>bold</b> ".to_string(),
+    };
+
+    assert_eq!(
+        trimmed.render(),
+        "<p>&lt;b&gt;bold&lt;/b&gt;</p><p> <b>bold</b> </p>"
+    );
+}
+
+#[test]
+fn unquoted_attributes_are_escaped() {
+    let link = Link {
+        title: "x onmouseover=alert(1)".to_string(),
+    };
+
+    assert_eq!(
+        link.render(),
+        r#"<a title="x&#x20;onmouseover&#x3D;alert(1)">link</a>"#
+    );
+}
+
+#[test]
+fn raw_interpolation_is_not_negation() {
+    let marked = Marked {
+        done: true,
+        html: "<b>bold</b>".to_string(),
+    };
+
+    assert_eq!(marked.render(), "<p>false <b>bold</b></p>");
+}
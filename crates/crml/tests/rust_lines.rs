@@ -0,0 +1,17 @@
+use crml::Template;
+
+#[derive(Template)]
+#[crml(
+    source = "%ul\n    - items.iter().try_for_each(|x| -> std::fmt::Result {\n        %li'{x}\n        - return Ok(())\n    - })?"
+)]
+struct List {
+    items: Vec<u8>,
+}
+
+#[test]
+fn writing_from_closures() {
+    assert_eq!(
+        List { items: vec![1, 2] }.render(),
+        "<ul><li>1</li><li>2</li></ul>"
+    );
+}
@@ -6,12 +6,7 @@ pub(crate) struct TestProps {
     a: i32,
 }
 
-#[template("other")]
-pub(crate) struct OtherProps {
-    c: i32,
-}
-
 fn main() {
-    println!("{}", "saved to ./simple.html");
+    println!("saved to ./simple.html");
     write("./simple.html", TestProps { a: 1 }.render()).expect("failed to write file");
 }